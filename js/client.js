@@ -32,8 +32,6 @@ var my_player_id;
 var socket;
 var socketOpen = false;
 
-var gameOver = false;
-
 var game_state = new GameState([], [], [], [], 0);
 // Actual Code
 
@@ -58,10 +56,6 @@ function play() {
 }
 
 function init() {
-    //TODO: Receive board specifications from server
-
-    board = Array(BOARD_WIDTH).fill(Array(BOARD_HEIGHT));
-
     canvas = document.getElementById('board');
     ctx = canvas.getContext('2d');
     ctx.strokeStyle = strokeStyle;
@@ -69,8 +63,7 @@ function init() {
     canvasWidth = canvas.width;
     canvasHeight = canvas.height;
 
-    blockHeight = canvasHeight / BOARD_HEIGHT;
-    blockWidth = canvasWidth / BOARD_WIDTH;
+    resizeBoard({width: BOARD_WIDTH, height: BOARD_HEIGHT});
 
     initKeypressHandler();
 
@@ -85,6 +78,20 @@ function init() {
     });
 }
 
+/**
+ *  Sets the board to the size the server sent in the init message.
+ *  Rows in the hidden buffer zone have negative y, so aren't drawn.
+ */
+function resizeBoard(size) {
+    BOARD_WIDTH = size.width;
+    BOARD_HEIGHT = size.height;
+
+    board = Array(BOARD_WIDTH).fill(Array(BOARD_HEIGHT));
+
+    blockHeight = canvasHeight / BOARD_HEIGHT;
+    blockWidth = canvasWidth / BOARD_WIDTH;
+}
+
 /**
  *  Delegates the logic for each frame and makes calls to the network
  *  and rendering logic to draw the frame and send data to the server
@@ -93,12 +100,8 @@ function handleFrame() {
     updatePosition();
     draw_frame();
 
-    // only paint another frame if the game isn't over
-    if (!gameOver) {
-        window.requestAnimationFrame(handleFrame);
-    } else {
-        $('#restart-modal').show();
-    }
+    // between games the board stays up while the room waits for players
+    window.requestAnimationFrame(handleFrame);
 }
 
 /**
@@ -19,16 +19,21 @@ class GameState {
   }
 
   static fromJson(json) {
-    const server_state = JSON.parse(json);
+    return GameState.fromServerState(JSON.parse(json));
+  }
 
+  static fromServerState(server_state) {
     let pieces = server_state.piece_states.map((x) => {
-      return PlayerPiece.fromNetworkInfo(
+      let piece = PlayerPiece.fromNetworkInfo(
         x.shape,
         x.pivot.x,
         x.pivot.y,
         x.rotation,
         x.player_id,
         x.player_name);
+      piece.held_shape = x.held_shape;
+      piece.ghost = x.ghost;
+      return piece;
     });
 
     let fallen_blocks = server_state.fallen_blocks.map((fallen_block) => {
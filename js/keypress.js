@@ -1,7 +1,12 @@
 // The keys which -- when depressed -- should trigger repeated keypress events.
 // All other events will trigger a keypress event just when they are pressed
 // for the first time.
-const REPEATED_PRESS_PERIOD = {'ArrowLeft': 100, 'ArrowRight': 100, 'ArrowUp': 200, 'z': 200};
+const REPEATED_PRESS_PERIOD = {'ArrowUp': 200, 'z': 200};
+
+// The keys the server repeats while they are held down (DAS and ARR), so we
+// only tell it when they go down and come back up.
+const SERVER_REPEATED_KEYS = {'ArrowLeft': 'left', 'ArrowRight': 'right', 'ArrowDown': 'fast_drop'};
+var server_keys_down = {};
 
 var keypress_timers = {};
 var keypresses = {};
@@ -17,6 +22,23 @@ function getKeypresses() {
 function initKeypressHandler() {
     // keydown map contains whether or not each key is down
     window.addEventListener('keydown', (e) => {
+        // ready up, or stop being ready, between games
+        if (e.key == 'Enter') {
+            if (!e.repeat) {
+                toggleReady();
+            }
+            return;
+        }
+
+        if (e.key in SERVER_REPEATED_KEYS) {
+            // ignore the browser's own key repeat
+            if (!server_keys_down[e.key]) {
+                server_keys_down[e.key] = true;
+                sendKeyEvent(SERVER_REPEATED_KEYS[e.key], true);
+            }
+            return;
+        }
+
         // if there's already a timer to fire this keypress, do nothing
         if (!(e.key in keypress_timers)) {
             // trigger a keypress event immediately
@@ -41,6 +63,14 @@ function initKeypressHandler() {
     });
 
     window.addEventListener('keyup', (e) => {
+        if (e.key in SERVER_REPEATED_KEYS) {
+            if (server_keys_down[e.key]) {
+                delete server_keys_down[e.key];
+                sendKeyEvent(SERVER_REPEATED_KEYS[e.key], false);
+            }
+            return;
+        }
+
         // this may be undefined if the key was pressed before the
         // program started
         if (e.key in keypress_timers) {
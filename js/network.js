@@ -10,16 +10,68 @@ function getMyPiece() {
 }
 
 
+/*
+The last full state received from the server, kept up to date by applying
+each gameDelta to it in sequence order.
+*/
+var server_state = null;
+
+/*
+Applies a gameDelta message to server_state. Returns false if a delta was
+missed, in which case server_state is stale and a resync is needed.
+*/
+function applyDelta(delta) {
+    if (delta.seq <= server_state.seq) {
+      return true; // already included in the snapshot
+    }
+    if (delta.seq != server_state.seq + 1) {
+      return false;
+    }
+
+    let blockKey = (position) => `${position.x},${position.y}`;
+    let fallen_blocks = new Map(server_state.fallen_blocks.map((b) => [blockKey(b.position), b]));
+    (delta.removed_blocks || []).forEach((position) => fallen_blocks.delete(blockKey(position)));
+    (delta.added_blocks || []).forEach((block) => fallen_blocks.set(blockKey(block.position), block));
+    server_state.fallen_blocks = [...fallen_blocks.values()];
+
+    let pieces = new Map(server_state.piece_states.map((p) => [p.player_id, p]));
+    (delta.removed_pieces || []).forEach((player_id) => pieces.delete(player_id));
+    (delta.moved_pieces || []).forEach((piece) => pieces.set(piece.player_id, piece));
+    server_state.piece_states = [...pieces.values()];
+
+    ['player_queue', 'piece_queue', 'score', 'seed', 'stats', 'input_frames'].forEach((field) => {
+      if (delta.hasOwnProperty(field)) {
+        server_state[field] = delta[field];
+      }
+    });
+
+    server_state.seq = delta.seq;
+    return true;
+}
+
 /*
 @connectionCallback: function called game_state has been receive from server
 */
 function initSocket(connectionCallback) {
     let hostname = location.hostname == "" ? "localhost" : location.hostname;
 
-    let websocketAddress = `ws://${hostname}:3012`;
+    // join the room given by ?room=<name>, or the default room
+    let room = new URLSearchParams(location.search).get('room');
+    let roomPath = room ? `/room/${encodeURIComponent(room)}` : '';
+
+    // the settings to create the room with, if it doesn't exist yet,
+    // and whether to play or spectate
+    let params = new URLSearchParams(location.search);
+    let options = ['randomizer', 'collision', 'width', 'height', 'buffer', 'role']
+        .filter((key) => params.get(key))
+        .map((key) => `${key}=${encodeURIComponent(params.get(key))}`);
+    let query = options.length > 0 ? `?${options.join('&')}` : '';
+
+    let websocketAddress = `ws://${hostname}:3012${roomPath}${query}`;
     console.log(`Connecting to WebSocket at: ${websocketAddress}`);
 
-    socket = new WebSocket(websocketAddress);
+    socket = new WebSocket(websocketAddress, wireProtocols());
+    socket.binaryType = 'arraybuffer';
     let made_callback = false;
 
     socket.onopen = function(e) {
@@ -27,15 +79,20 @@ function initSocket(connectionCallback) {
     };
 
     socket.onmessage = function(event) {
-        let message = JSON.parse(event.data);
+        let message = decodeMessage(event.data);
 
         switch (message.type) {
           case 'init':
             my_player_id = message.player_id;
+            my_role = message.role;
+            if (message.board) {
+              resizeBoard(message.board);
+            }
             break;
 
           case 'gameState':
-            game_state = GameState.fromJson(event.data);
+            server_state = message;
+            game_state = GameState.fromServerState(server_state);
 
             if (!made_callback) {
               made_callback = true;
@@ -43,8 +100,31 @@ function initSocket(connectionCallback) {
             }
             break;
 
+          case 'gameDelta':
+            // ignore deltas until we have a snapshot to apply them to
+            if (server_state == null) {
+              break;
+            }
+            if (applyDelta(message)) {
+              game_state = GameState.fromServerState(server_state);
+              showEvents(message.events || []);
+            } else {
+              // we missed a delta, so ask for the full state again
+              server_state = null;
+              socket.send(JSON.stringify({type: 'resync'}));
+            }
+            break;
+
+          case 'role':
+            my_role = message.role;
+            break;
+
           case 'gameOver':
-            gameOver = true;
+            showStats(message.stats || []);
+            break;
+
+          case 'lobby':
+            showLobby(message);
             break;
           default:
             console.error(`Invalid message type ${message.type} received from server.`);
@@ -66,6 +146,79 @@ function initSocket(connectionCallback) {
     };
 }
 
+/*
+Whether we are playing or just watching, and a way to switch between them
+mid-game. A player who starts spectating gives up their piece.
+*/
+var my_role = 'player';
+
+function setRole(role) {
+    if (!socketOpen) {
+      return;
+    }
+    socket.send(JSON.stringify({type: 'join', role: role}));
+}
+
+/*
+Where the room is between games (waiting, countdown, playing or gameOver),
+and whether we have said we are ready for the next one. The server only
+sends the time left when the phase changes, so the countdown is kept
+ticking here until the next lobby message.
+*/
+var lobby = {phase: 'waiting', players: [], ready: []};
+var lobby_ends_at = 0;
+var lobby_timer = null;
+
+function showLobby(message) {
+    lobby = message;
+    lobby_ends_at = Date.now() + (lobby.remaining_millis || 0);
+
+    clearInterval(lobby_timer);
+    lobby_timer = null;
+    if (lobby.phase == 'countdown') {
+      lobby_timer = setInterval(drawLobby, 100);
+    }
+    drawLobby();
+}
+
+function drawLobby() {
+    let ready = lobby.ready.includes(my_player_id);
+    let text = '';
+    switch (lobby.phase) {
+      case 'waiting':
+        text = `${lobby.ready.length}/${lobby.players.length} players ready, ` +
+               `at least ${lobby.min_players} needed<br>` +
+               (ready ? 'Press Enter if you are not ready' : 'Press Enter when you are ready');
+        break;
+      case 'countdown': {
+        let remaining_millis = Math.max(lobby_ends_at - Date.now(), 0);
+        text = `Starting in ${Math.ceil(remaining_millis / 1000)}...`;
+        break;
+      }
+      case 'gameOver':
+        text = 'Game over';
+        break;
+    }
+    if (my_role == 'spectator') {
+      text = lobby.phase == 'playing' ? '' : `Spectating: ${lobby.phase}`;
+    }
+    $("#lobby").html(text);
+}
+
+function toggleReady() {
+    if (!socketOpen || my_role == 'spectator') {
+      return;
+    }
+    let ready = lobby.ready.includes(my_player_id);
+    socket.send(JSON.stringify({type: 'ready', ready: !ready}));
+}
+
+/*
+The number of inputs sent so far, used to stamp each one so the server can
+apply them in order and tell us which it has applied (input_frames).
+*/
+var input_frame = 0;
+
 function sendInput(inputs) {
     let convertedArr = {};
     convertedArr.left = inputs.ArrowLeft || false;
@@ -74,8 +227,31 @@ function sendInput(inputs) {
     convertedArr.rot = inputs.z || false;
     convertedArr.hard_drop = inputs[' '] || false;
     convertedArr.fast_drop = inputs.ArrowDown || false;
+    convertedArr.hold = inputs.c || inputs.Shift || false;
     convertedArr.player_id = my_player_id;
     convertedArr.player_name = name;
+
+    // the server ignores inputs which don't press anything
+    let pressed = Object.keys(convertedArr).some((key) => convertedArr[key] === true);
+    if (!pressed) {
+      return;
+    }
+    input_frame += 1;
+    convertedArr.frame = input_frame;
+
     let message = JSON.stringify(convertedArr);
     socket.send(message);
 }
+
+/*
+Tells the server that a key it repeats (see SERVER_REPEATED_KEYS) went down
+or came back up.
+*/
+function sendKeyEvent(key, pressed) {
+    if (!socketOpen) {
+      return;
+    }
+
+    input_frame += 1;
+    socket.send(JSON.stringify({type: 'key', key: key, pressed: pressed, frame: input_frame}));
+}
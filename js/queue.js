@@ -157,7 +157,62 @@ function drawBlockQueue() {
   }
 }
 
+// draws the piece in my hold slot, if I'm playing and holding one
+function drawHoldPiece() {
+  let myPiece = getMyPiece();
+  let held_shape = myPiece ? myPiece.held_shape : null;
+
+  if (held_shape == null) {
+    $("#hold-piece .shape").html("");
+  } else {
+    $("#hold-piece .shape").html(getPieceHTML(held_shape));
+  }
+}
+
+const EVENT_DISPLAY_MS = 2000;
+var event_timer = null;
+
+// the text announcing an event sent by the server
+function describeEvent(event) {
+  switch (event.event) {
+    case 'tSpin':
+      let lines = ['', ' Single', ' Double', ' Triple'][event.lines] || '';
+      let kind = event.kind == 'mini' ? 'Mini T-Spin' : 'T-Spin';
+      return `${kind}${lines}! +${event.points}`;
+    case 'combo':
+      return `Combo x${event.count}! +${event.points}`;
+    case 'backToBack':
+      return `Back-to-Back! +${event.points}`;
+    case 'perfectClear':
+      return `Perfect Clear! +${event.points}`;
+    default:
+      return null;
+  }
+}
+
+// shows the latest events for a couple of seconds
+function showEvents(events) {
+  let text = events.map(describeEvent).filter((x) => x != null).join('<br>');
+  if (text == '') {
+    return;
+  }
+
+  $("#events").html(text);
+  clearTimeout(event_timer);
+  event_timer = setTimeout(() => $("#events").html(''), EVENT_DISPLAY_MS);
+}
+
+// shows what every player did during the game that just ended
+function showStats(stats) {
+  let rows = stats.map((player) =>
+    `Player ${player.player_id}: ${player.points} points, ${player.lines_cleared} lines, ` +
+    `${player.tetrises} tetrises, ${player.pieces_placed} pieces, ${Math.round(player.apm)} APM`);
+  $("#events").html(rows.join('<br>'));
+  clearTimeout(event_timer);
+}
+
 function updateQueue() {
   drawMyPiece();
   drawBlockQueue();
+  drawHoldPiece();
 }
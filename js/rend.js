@@ -18,7 +18,7 @@ function drawShadowBlock(x, y) {
 
     // Draw the blocks in the shape
     let posX = canvasWidth * x / BOARD_WIDTH;
-    let posY = canvasHeight * y / BOARD_WIDTH;
+    let posY = canvasHeight * y / BOARD_HEIGHT;
     ctx.fillRect(posX, posY, blockWidth, blockHeight);
 }
 
@@ -37,7 +37,7 @@ function drawBlock(x, y, color, glow) {
 
     // Draw the blocks in the shape
     let posX = canvasWidth * x / BOARD_WIDTH;
-    let posY = canvasHeight * y / BOARD_WIDTH;
+    let posY = canvasHeight * y / BOARD_HEIGHT;
     ctx.fillRect(posX, posY, blockWidth, blockHeight);
 
     // set the painting context again for no shadow
@@ -59,7 +59,7 @@ function drawPieces() {
       });
 
       let nameX = canvasWidth * cor_x / BOARD_WIDTH;
-      let nameY = canvasHeight * min_y / BOARD_WIDTH;
+      let nameY = canvasHeight * min_y / BOARD_HEIGHT;
 
       ctx.fillStyle = "#FFFFFF";
       ctx.font = "bold 10pt Courier";
@@ -84,20 +84,13 @@ function drawMyPieceShadow() {
     // sometimes we'll be in the queue and won't have a shadow to draw
     if (my_piece == undefined) return;
 
-    // if for some reason my piece is currently colliding with something,
-    // don't draw a shadow
-    if (my_piece.collision()) return;
+    // the server sends where each piece would land, taking the stack
+    // and everyone else's pieces into account
+    if (!my_piece.ghost) return;
 
-    // make a test piece and move it down until it hits something,
-    // then back up by 1 block so we are no longer colliding
     let test_piece = my_piece.deepCopy();
-
-
-    while (!test_piece.collision()) {
-        test_piece.y += 1;
-    }
-    test_piece.y -= 1;
-
+    test_piece.x = my_piece.ghost.x;
+    test_piece.y = my_piece.ghost.y;
 
     // Draw the blocks in the shape
     test_piece.get_occupied_blocks((x, y) => {
@@ -139,7 +132,7 @@ function initGrid() {
         ctx.lineTo(posX, canvasHeight);
     }
     // Draw Horizontal Gridlines
-    for(let i = 0; i <= BOARD_WIDTH; ++i) {
+    for(let i = 0; i <= BOARD_HEIGHT; ++i) {
         let posY = i * blockHeight;
         ctx.moveTo(0, posY);
         ctx.lineTo(canvasWidth, posY);
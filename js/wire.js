@@ -0,0 +1,120 @@
+/*jshint esversion: 6 */
+
+/*
+The WebSocket subprotocols the server understands. We ask for MessagePack
+binary frames, which are much smaller than JSON text, unless the page was
+opened with ?encoding=json.
+*/
+const JSON_PROTOCOL = 'tetris.json';
+const MSGPACK_PROTOCOL = 'tetris.msgpack';
+
+function wireProtocols() {
+    let encoding = new URLSearchParams(location.search).get('encoding');
+    return encoding == 'json' ? [JSON_PROTOCOL] : [MSGPACK_PROTOCOL, JSON_PROTOCOL];
+}
+
+/*
+MessagePack frames send these map keys as their index in this list instead
+of by name. It must match KEYS in rust/src/wire/mod.rs.
+*/
+const WIRE_KEYS = [
+    // gameState and gameDelta
+    'type', 'version', 'seq', 'piece_states', 'fallen_blocks', 'player_queue',
+    'piece_queue', 'score', 'seed', 'board', 'stats', 'input_frames',
+    'added_blocks', 'removed_blocks', 'moved_pieces', 'removed_pieces', 'events',
+    // pieces and blocks
+    'shape', 'pivot', 'rotation', 'player_id', 'player_name', 'ghost', 'x', 'y',
+    'original_shape', 'position', 'width', 'height', 'buffer',
+    // stats and events
+    'pieces_placed', 'lines_cleared', 'tetrises', 'hard_drops', 'points',
+    'actions', 'apm', 'event', 'playerId', 'kind', 'lines', 'count',
+    // init, role and lobby
+    'room', 'randomizer', 'collision', 'role', 'phase', 'players', 'ready',
+    'min_players', 'remaining_millis',
+    // inputs and other messages from clients
+    'left', 'right', 'rot', 'counter_rot', 'hard_drop', 'fast_drop', 'hold',
+    'frame', 'key', 'pressed', 'das_millis', 'arr_millis', 'soft_drop_millis',
+];
+
+/*
+Parses a message from a text (JSON) or binary (MessagePack) frame.
+*/
+function decodeMessage(data) {
+    if (typeof data == 'string') {
+      return JSON.parse(data);
+    }
+    return decodeMessagePack(new DataView(data));
+}
+
+/*
+Reads a MessagePack value out of view, turning key indexes back into names.
+Only the types the server sends are supported.
+*/
+function decodeMessagePack(view) {
+    let offset = 0;
+    let textDecoder = new TextDecoder();
+
+    let take = (length) => {
+      offset += length;
+      return offset - length;
+    };
+    let readString = (length) => {
+      let start = take(length);
+      return textDecoder.decode(new Uint8Array(view.buffer, view.byteOffset + start, length));
+    };
+    let readArray = (length) => {
+      let values = [];
+      for (let i = 0; i < length; i++) {
+        values.push(read());
+      }
+      return values;
+    };
+    let readMap = (length) => {
+      let map = {};
+      for (let i = 0; i < length; i++) {
+        let key = read();
+        if (typeof key == 'number') {
+          key = WIRE_KEYS[key] || String(key);
+        }
+        map[key] = read();
+      }
+      return map;
+    };
+
+    let read = () => {
+      let type = view.getUint8(take(1));
+
+      if (type <= 0x7f) return type;
+      if (type >= 0xe0) return type - 0x100;
+      if ((type & 0xf0) == 0x80) return readMap(type & 0x0f);
+      if ((type & 0xf0) == 0x90) return readArray(type & 0x0f);
+      if ((type & 0xe0) == 0xa0) return readString(type & 0x1f);
+
+      switch (type) {
+        case 0xc0: return null;
+        case 0xc2: return false;
+        case 0xc3: return true;
+        case 0xca: return view.getFloat32(take(4));
+        case 0xcb: return view.getFloat64(take(8));
+        case 0xcc: return view.getUint8(take(1));
+        case 0xcd: return view.getUint16(take(2));
+        case 0xce: return view.getUint32(take(4));
+        case 0xcf: return Number(view.getBigUint64(take(8)));
+        case 0xd0: return view.getInt8(take(1));
+        case 0xd1: return view.getInt16(take(2));
+        case 0xd2: return view.getInt32(take(4));
+        case 0xd3: return Number(view.getBigInt64(take(8)));
+        case 0xd9: return readString(view.getUint8(take(1)));
+        case 0xda: return readString(view.getUint16(take(2)));
+        case 0xdb: return readString(view.getUint32(take(4)));
+        case 0xdc: return readArray(view.getUint16(take(2)));
+        case 0xdd: return readArray(view.getUint32(take(4)));
+        case 0xde: return readMap(view.getUint16(take(2)));
+        case 0xdf: return readMap(view.getUint32(take(4)));
+        default:
+          throw new Error(`Unsupported MessagePack type 0x${type.toString(16)}`);
+      }
+    };
+
+    return read();
+}
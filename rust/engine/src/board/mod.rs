@@ -0,0 +1,120 @@
+use std::ops::RangeInclusive;
+
+use serde::{Deserialize, Serialize};
+
+use crate::piece_state::{PieceState, Pivot, FallenBlocksType};
+use crate::tetris::fallen_blocks_collision;
+
+// limits on the dimensions a room can ask for, the board must be wide
+// enough for at least two spawn lanes
+pub const WIDTH_RANGE : RangeInclusive<i8> = 10..=40;
+pub const HEIGHT_RANGE : RangeInclusive<i8> = 10..=40;
+pub const BUFFER_RANGE : RangeInclusive<i8> = 0..=10;
+
+// pieces spawn at most this many rows above the visible field
+const SPAWN_ROWS : i8 = 2;
+
+// lanes are centred on a 3-wide piece, I and O pieces stick out to the right
+const SPAWN_WIDTH : i8 = 3;
+
+// every spawn lane is at least this wide, so that pieces spawning in
+// neighbouring lanes (even I pieces) have room to turn
+const MIN_LANE_WIDTH : i8 = 5;
+
+/**
+ *
+ *  The size of a game's board. Rows 0 to height - 1 are visible, and
+ *  a hidden buffer zone of buffer rows above them (with negative y)
+ *  is where pieces spawn.
+ *
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Board {
+    pub width: i8,
+    pub height: i8,
+    pub buffer: i8,
+}
+
+impl Default for Board {
+    fn default() -> Board {
+        Board {
+            width: 20,
+            height: 20,
+            buffer: 2,
+        }
+    }
+}
+
+impl Board {
+    // the highest row a piece can occupy
+    pub fn top(&self) -> i8 {
+        return -self.buffer;
+    }
+
+    pub fn in_columns(&self, x: i8) -> bool {
+        return (0..self.width).contains(&x);
+    }
+
+    // the row new pieces are placed at, just above the visible field
+    pub fn spawn_y(&self) -> i8 {
+        return -self.buffer.min(SPAWN_ROWS);
+    }
+
+    // how many pieces can spawn side by side
+    pub fn lane_count(&self) -> usize {
+        return (self.width / MIN_LANE_WIDTH).max(1) as usize;
+    }
+
+    /**
+     *
+     *  How many pieces can be in play at once with this many players
+     *  in the room. Every player can play at once until the board runs
+     *  out of lanes, then the rest wait their turn.
+     *
+     */
+    pub fn max_active(&self, num_players: usize) -> usize {
+        return num_players.min(self.lane_count());
+    }
+
+    /**
+     *
+     *  Where new pieces are placed, one in the middle of each lane, from
+     *  left to right. The lanes split the board evenly, and any columns
+     *  left over are shared between the two edges.
+     *
+     */
+    pub fn spawn_positions(&self) -> Vec<Pivot> {
+        let lanes = self.lane_count() as i8;
+        let lane_width = self.width / lanes;
+        let margin = (self.width - lane_width * lanes) / 2;
+
+        return (0..lanes).map(|lane| {
+            return Pivot {
+                x: margin + lane * lane_width + (lane_width - SPAWN_WIDTH) / 2,
+                y: self.spawn_y(),
+            };
+        }).collect();
+    }
+
+    /**
+     *
+     *  Whether the game is over: either a block locked in the hidden
+     *  buffer zone, or the stack is in the way of the next piece, of
+     *  shape next_shape, in every spawn lane. A piece which only has
+     *  some lanes blocked spawns in one of the others.
+     *
+     */
+    pub fn is_game_over(&self, fallen_blocks: &FallenBlocksType, next_shape: u8) -> bool {
+        if fallen_blocks.keys().any(|pivot| pivot.y < 0) {
+            return true;
+        }
+
+        return self.spawn_positions().iter().all(|spawn| {
+            let mut piece = PieceState::new(0);
+            piece.shape = next_shape;
+            piece.pivot = *spawn;
+            return fallen_blocks_collision(&piece, fallen_blocks, self);
+        });
+    }
+}
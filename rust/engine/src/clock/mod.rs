@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+// the most ticks run to catch up in one go, if the game thread falls
+// further behind than this the game slows down instead
+pub const MAX_CATCH_UP_TICKS : u64 = 10;
+
+/**
+ *
+ *  The source of time for a game. Times are milliseconds since the
+ *  clock started, and never go backwards.
+ *
+ */
+pub trait Clock: Send + Sync {
+    fn now(&self) -> u128;
+}
+
+/**
+ *
+ *  Real time, measured with Instant so that changes to the system
+ *  clock (e.g. by NTP) don't affect the game.
+ *
+ */
+pub struct MonotonicClock {
+    start: Instant,
+}
+
+impl Default for MonotonicClock {
+    fn default() -> MonotonicClock {
+        MonotonicClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> u128 {
+        return self.start.elapsed().as_millis();
+    }
+}
+
+/**
+ *
+ *  Schedules the ticks of a game at a fixed timestep. Ticks are due at
+ *  exact multiples of the frame time, so time spent running them (or
+ *  sleeping for too long) doesn't make the game drift slower, and a
+ *  thread which falls behind runs extra ticks to catch up.
+ *
+ */
+pub struct Ticker {
+    frame_millis: u128,
+
+    // when the next tick is due
+    next_tick: u128,
+}
+
+impl Ticker {
+    pub fn new(frame_millis: u64, now: u128) -> Ticker {
+        Ticker {
+            frame_millis: frame_millis as u128,
+            next_tick: now + frame_millis as u128,
+        }
+    }
+
+    /**
+     *
+     *  How many ticks should be run now. If more than
+     *  MAX_CATCH_UP_TICKS are due, the rest are skipped.
+     *
+     */
+    pub fn due(&mut self, now: u128) -> u64 {
+        if now < self.next_tick {
+            return 0;
+        }
+
+        let due = ((now - self.next_tick) / self.frame_millis + 1) as u64;
+        if due > MAX_CATCH_UP_TICKS {
+            self.next_tick = now + self.frame_millis;
+            return MAX_CATCH_UP_TICKS;
+        }
+
+        self.next_tick += due as u128 * self.frame_millis;
+        return due;
+    }
+
+    // how long to wait until the next tick is due
+    pub fn until_next(&self, now: u128) -> u64 {
+        return self.next_tick.saturating_sub(now) as u64;
+    }
+}
+
+/**
+ *
+ *  A clock which only moves when told to, so tests (here or in the
+ *  server) can step through time deterministically.
+ *
+ */
+#[derive(Default)]
+pub struct ManualClock {
+    millis: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn advance(&self, millis: u64) {
+        self.millis.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u128 {
+        return self.millis.load(Ordering::SeqCst) as u128;
+    }
+}
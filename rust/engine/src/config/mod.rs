@@ -0,0 +1,326 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as DeError;
+
+use crate::board::{Board, WIDTH_RANGE, HEIGHT_RANGE, BUFFER_RANGE};
+use crate::handling::{DAS_RANGE, ARR_RANGE, SOFT_DROP_RANGE};
+use crate::randomizer::RandomizerKind;
+use crate::scoring::ScoringTable;
+use crate::tetris::CollisionPolicy;
+
+// environment variable naming the configuration file
+const CONFIG_ENV_VAR : &str = "TETRIS_CONFIG";
+
+// every other setting can be overridden by an environment variable
+// named after it, e.g. TETRIS_FRAME_MILLIS
+const ENV_VAR_PREFIX : &str = "TETRIS_";
+
+// the settings which can be given on the command line or in the
+// environment, see Config::set
+pub const OVERRIDABLE_KEYS : [&str ; 19] = [
+    "frame_millis",
+    "max_shift_period",
+    "min_shift_period",
+    "speed_capped_lines",
+    "bottom_touch_millis",
+    "max_lock_resets",
+    "fast_drop_shift_millis",
+    "das_millis",
+    "arr_millis",
+    "max_inputs_per_tick",
+    "max_queued_inputs",
+    "max_active",
+    "collision",
+    "deadlock_millis",
+    "seed",
+    "randomizer",
+    "width",
+    "height",
+    "buffer",
+];
+
+/**
+ *
+ *  Everything about how a game plays which can be changed without
+ *  recompiling. Settings are read from a TOML file, then overridden by
+ *  environment variables, then by command-line flags. Any setting
+ *  left out keeps its default value.
+ *
+ */
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // the length of one tick of the game, which runs at a fixed timestep
+    pub frame_millis: u64,
+
+    // how long pieces take to fall 1 square at the start of a game,
+    // and once speed_capped_lines lines have been cleared
+    pub max_shift_period: f32,
+    pub min_shift_period: f32,
+    pub speed_capped_lines: u32,
+
+    // how long a piece may move when touching the bottom of the board before it freezes
+    pub bottom_touch_millis: u64,
+
+    // how many times moving or rotating a piece which is touching the
+    // bottom can start bottom_touch_millis again, until it falls further
+    pub max_lock_resets: u32,
+
+    // how long a piece takes to fall 1 square while fast dropping
+    pub fast_drop_shift_millis: u64,
+
+    // how held keys repeat for players who don't pick their own
+    // handling, see handling::Handling
+    pub das_millis: u64,
+    pub arr_millis: u64,
+
+    // how many of each player's queued inputs are applied per tick,
+    // the rest wait for the next one
+    pub max_inputs_per_tick: usize,
+
+    // how many of each player's inputs can wait to be applied, so that a
+    // client sending faster than the game ticks can't build up a backlog
+    pub max_queued_inputs: usize,
+
+    // if set, no more than this many pieces are in play at once,
+    // however many lanes the board has
+    pub max_active: Option<usize>,
+
+    // how the players' pieces treat each other, for rooms which don't ask
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub collision: CollisionPolicy,
+
+    // how long pieces which hold each other up wait before the one
+    // trying to fall locks where it is, so they can't block each other forever
+    pub deadlock_millis: u64,
+
+    // if set, every new room starts with this seed instead of a random one
+    pub seed: Option<u64>,
+
+    // the randomizer and board size for rooms which don't ask for their own
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub randomizer: RandomizerKind,
+    pub board: Board,
+
+    pub scoring: ScoringTable,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            frame_millis: (1000.0 / 60.0) as u64,
+            max_shift_period: 400.0,
+            min_shift_period: 100.0,
+            speed_capped_lines: 100,
+            bottom_touch_millis: 500,
+            max_lock_resets: 15,
+            fast_drop_shift_millis: 25,
+            das_millis: 167, // 10 frames
+            arr_millis: 33, // 2 frames
+            max_inputs_per_tick: 1,
+            max_queued_inputs: 32,
+            max_active: None,
+            collision: CollisionPolicy::default(),
+            deadlock_millis: 3000, // 3 seconds
+            seed: None,
+            randomizer: RandomizerKind::default(),
+            board: Board::default(),
+            scoring: ScoringTable::default(),
+        }
+    }
+}
+
+/**
+ *
+ *  A configuration which couldn't be loaded, and why.
+ *
+ */
+#[derive(Debug, PartialEq)]
+pub struct ConfigError(pub String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/**
+ *
+ *  Settings which can be loaded from a TOML file, the environment and
+ *  the command line. Config is the game's settings, and the server
+ *  wraps it in its own settings for things like the address it listens
+ *  on, so that both are loaded from the same file and flags.
+ *
+ */
+pub trait Settings: Default {
+    // the settings which can be given on the command line or in the environment
+    fn keys() -> Vec<&'static str>;
+
+    fn from_toml(contents: &str) -> Result<Self, ConfigError>;
+
+    // overrides a single setting, given its name and its value as text
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError>;
+
+    // checks that the settings make sense together
+    fn validate(&self) -> Result<(), ConfigError>;
+
+    /**
+     *
+     *  Loads the settings from the file given by --config or
+     *  TETRIS_CONFIG (if any), then applies the overrides from the
+     *  environment and from args, the command-line arguments without
+     *  the program name.
+     *
+     */
+    fn load<I: Iterator<Item = String>>(args: I) -> Result<Self, ConfigError> {
+        let flags = parse_flags(args)?;
+        let keys = Self::keys();
+
+        let path = flags.iter()
+            .find(|(key, _)| key == "config")
+            .map(|(_, value)| value.clone())
+            .or_else(|| std::env::var(CONFIG_ENV_VAR).ok());
+
+        let mut settings = match path {
+            Some(path) => {
+                let contents = std::fs::read_to_string(&path)
+                    .map_err(|e| ConfigError(format!("unable to read {}: {}", path, e)))?;
+                Self::from_toml(&contents)
+                    .map_err(|e| ConfigError(format!("{}: {}", path, e)))?
+            },
+            None => Self::default(),
+        };
+
+        for key in keys.iter() {
+            let var = format!("{}{}", ENV_VAR_PREFIX, key.to_uppercase());
+            if let Ok(value) = std::env::var(&var) {
+                settings.set(key, &value).map_err(|e| ConfigError(format!("{}: {}", var, e)))?;
+            }
+        }
+
+        for (key, value) in flags.iter().filter(|(key, _)| key != "config") {
+            let flag = format!("--{}", key.replace('_', "-"));
+            if !keys.contains(&key.as_str()) {
+                return Err(ConfigError(format!("{}: unknown setting {:?}, expected one of {}", flag, key, keys.join(", "))));
+            }
+            settings.set(key, value).map_err(|e| ConfigError(format!("{}: {}", flag, e)))?;
+        }
+
+        settings.validate()?;
+        return Ok(settings);
+    }
+}
+
+impl Settings for Config {
+    fn keys() -> Vec<&'static str> {
+        return OVERRIDABLE_KEYS.to_vec();
+    }
+
+    fn from_toml(contents: &str) -> Result<Config, ConfigError> {
+        return toml::from_str(contents).map_err(|e| ConfigError(e.to_string()));
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "frame_millis" => self.frame_millis = parse(value)?,
+            "max_shift_period" => self.max_shift_period = parse(value)?,
+            "min_shift_period" => self.min_shift_period = parse(value)?,
+            "speed_capped_lines" => self.speed_capped_lines = parse(value)?,
+            "bottom_touch_millis" => self.bottom_touch_millis = parse(value)?,
+            "max_lock_resets" => self.max_lock_resets = parse(value)?,
+            "fast_drop_shift_millis" => self.fast_drop_shift_millis = parse(value)?,
+            "das_millis" => self.das_millis = parse(value)?,
+            "arr_millis" => self.arr_millis = parse(value)?,
+            "max_inputs_per_tick" => self.max_inputs_per_tick = parse(value)?,
+            "max_queued_inputs" => self.max_queued_inputs = parse(value)?,
+            "max_active" => self.max_active = Some(parse(value)?),
+            "collision" => self.collision = parse(value)?,
+            "deadlock_millis" => self.deadlock_millis = parse(value)?,
+            "seed" => self.seed = Some(parse(value)?),
+            "randomizer" => self.randomizer = parse(value)?,
+            "width" => self.board.width = parse(value)?,
+            "height" => self.board.height = parse(value)?,
+            "buffer" => self.board.buffer = parse(value)?,
+            _ => return Err(ConfigError(format!("unknown setting {:?}, expected one of {}",
+                                                key, OVERRIDABLE_KEYS.join(", ")))),
+        }
+        return Ok(());
+    }
+
+    // fails at startup rather than part way through a game
+    fn validate(&self) -> Result<(), ConfigError> {
+        let check = |ok: bool, message: String| {
+            return if ok { Ok(()) } else { Err(ConfigError(message)) };
+        };
+
+        check(self.frame_millis > 0 && self.frame_millis <= 1000,
+              format!("frame_millis must be between 1 and 1000, got {}", self.frame_millis))?;
+        check(self.min_shift_period > 0.0 && self.min_shift_period <= self.max_shift_period,
+              format!("min_shift_period must be positive and no more than max_shift_period ({}), got {}",
+                      self.max_shift_period, self.min_shift_period))?;
+        check(self.speed_capped_lines > 0,
+              format!("speed_capped_lines must be positive, got {}", self.speed_capped_lines))?;
+        check(DAS_RANGE.contains(&self.das_millis),
+              format!("das_millis must be between {} and {}, got {}", DAS_RANGE.start(), DAS_RANGE.end(), self.das_millis))?;
+        check(ARR_RANGE.contains(&self.arr_millis),
+              format!("arr_millis must be between {} and {}, got {}", ARR_RANGE.start(), ARR_RANGE.end(), self.arr_millis))?;
+        check(SOFT_DROP_RANGE.contains(&self.fast_drop_shift_millis),
+              format!("fast_drop_shift_millis must be between {} and {}, got {}",
+                      SOFT_DROP_RANGE.start(), SOFT_DROP_RANGE.end(), self.fast_drop_shift_millis))?;
+        check(self.max_inputs_per_tick > 0,
+              "max_inputs_per_tick must be at least 1".to_string())?;
+        check(self.max_queued_inputs > 0, "max_queued_inputs must be at least 1".to_string())?;
+        check(self.max_active != Some(0), "max_active must be at least 1".to_string())?;
+        check(self.deadlock_millis > 0, "deadlock_millis must be at least 1".to_string())?;
+        check(WIDTH_RANGE.contains(&self.board.width),
+              format!("width must be between {} and {}, got {}", WIDTH_RANGE.start(), WIDTH_RANGE.end(), self.board.width))?;
+        check(HEIGHT_RANGE.contains(&self.board.height),
+              format!("height must be between {} and {}, got {}", HEIGHT_RANGE.start(), HEIGHT_RANGE.end(), self.board.height))?;
+        check(BUFFER_RANGE.contains(&self.board.buffer),
+              format!("buffer must be between {} and {}, got {}", BUFFER_RANGE.start(), BUFFER_RANGE.end(), self.board.buffer))?;
+        check(self.scoring.back_to_back_percent >= 100,
+              format!("scoring.back_to_back_percent must be at least 100, got {}", self.scoring.back_to_back_percent))?;
+        return Ok(());
+    }
+}
+
+// parses a setting, with an error which says what was expected
+pub fn parse<T: FromStr>(value: &str) -> Result<T, ConfigError> where T::Err: fmt::Display {
+    return value.parse::<T>().map_err(|e| ConfigError(format!("invalid value {:?}: {}", value, e)));
+}
+
+fn deserialize_from_str<'de, D: Deserializer<'de>, T: FromStr>(deserializer: D) -> Result<T, D::Error>
+        where T::Err: fmt::Display {
+    let value = String::deserialize(deserializer)?;
+    return value.parse::<T>().map_err(D::Error::custom);
+}
+
+/**
+ *
+ *  Splits command-line arguments such as "--frame-millis 10" or
+ *  "--seed=5" into (setting name, value) pairs.
+ *
+ */
+fn parse_flags<I: Iterator<Item = String>>(mut args: I) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut flags = vec![];
+
+    while let Some(arg) = args.next() {
+        let flag = match arg.strip_prefix("--") {
+            Some(flag) => flag,
+            None => return Err(ConfigError(format!("unexpected argument {:?}, settings are given as --<name> <value>", arg))),
+        };
+
+        let (key, value) = match flag.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => match args.next() {
+                Some(value) => (flag.to_string(), value),
+                None => return Err(ConfigError(format!("--{} needs a value", flag))),
+            },
+        };
+        flags.push((key.replace('-', "_"), value));
+    }
+
+    return Ok(flags);
+}
@@ -0,0 +1,992 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::piece_state::{PieceState, Pivot, FallenBlock, ActivePlayersType, InactivePlayersType, FallenBlocksType};
+use crate::handling::{Handling, HandlingUpdate, HeldKeys};
+use crate::input::{Input, Key, KeyEvent};
+use crate::tetris::{screen_collision, CollisionType, update_state, landing_position, bottom_row, is_grounded, track_lowest_row, start_lock_delay, pieces_overlap, CollisionPolicy, fallen_blocks_collision, player_collision, collision, clear_lines, detect_t_spin, read_block, get_shape};
+use crate::randomizer::PieceQueue;
+use crate::scoring::{GameEvent, Score};
+use crate::snapshot::GameSnapshot;
+use crate::stats::PlayerStats;
+
+/**
+ *
+ *  Everything about a single game, advanced one fixed step at a time
+ *  by tick. Nothing in here knows about connections or real time, so
+ *  the same inputs always play out the same way.
+ *
+ */
+pub struct GameState {
+    pub config: Config,
+
+    pub active_players: ActivePlayersType,
+    pub inactive_players: InactivePlayersType,
+    pub fallen_blocks: FallenBlocksType,
+    pub score: Score,
+    pub piece_queue: PieceQueue,
+
+    // the seed of the current game's piece generator
+    pub seed: u64,
+
+    // how many ticks have run, which is how the game measures time
+    pub ticks: u64,
+
+    // each player's inputs which haven't been applied yet, in order
+    input_queues: HashMap<usize, VecDeque<Input>>,
+
+    // the frame stamp of the last input applied for each player, so
+    // clients know which of their inputs the game state includes
+    pub input_frames: HashMap<usize, u64>,
+
+    // the handling of players who picked their own, and the keys each
+    // player is holding down
+    handling: HashMap<usize, Handling>,
+    held_keys: HashMap<usize, HeldKeys>,
+
+    // the time when a piece was last spawned
+    last_spawn_time: u128,
+}
+
+/**
+ *
+ *  What happened during a tick which the players need to be told
+ *  about, besides the changes to the game state.
+ *
+ */
+#[derive(Debug, Default)]
+pub struct Events {
+    pub game_events: Vec<GameEvent>,
+
+    // set when the tick ended the game, the state has already been
+    // reset for the next one
+    pub game_over: Option<GameOver>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct GameOver {
+    pub score: u32,
+    pub stats: Vec<PlayerStats>,
+}
+
+impl GameState {
+    pub fn new(config: Config, seed: u64) -> GameState {
+        GameState {
+            active_players: HashMap::new(),
+            inactive_players: VecDeque::new(),
+            fallen_blocks: HashMap::new(),
+            score: Score::new(config.scoring.clone()),
+            piece_queue: PieceQueue::new(config.randomizer, seed),
+            seed,
+            ticks: 0,
+            input_queues: HashMap::new(),
+            input_frames: HashMap::new(),
+            handling: HashMap::new(),
+            held_keys: HashMap::new(),
+            last_spawn_time: 0,
+            config,
+        }
+    }
+
+    // the game time in milliseconds, a fixed frame_millis per tick
+    pub fn now(&self) -> u128 {
+        return self.ticks as u128 * self.config.frame_millis as u128;
+    }
+
+    // adds a player to the back of the queue to play
+    pub fn add_player(&mut self, player_id: usize) {
+        // a "null" struct, this will be set properly when the piece becomes active
+        self.inactive_players.push_back(PieceState::new(player_id));
+    }
+
+    pub fn remove_player(&mut self, player_id: usize) {
+        remove_player(player_id, &mut self.active_players, &mut self.inactive_players);
+        self.input_queues.remove(&player_id);
+        self.input_frames.remove(&player_id);
+        self.handling.remove(&player_id);
+        self.held_keys.remove(&player_id);
+    }
+
+    // the players in the game, those with a piece in play first
+    pub fn player_ids(&self) -> Vec<usize> {
+        let mut player_ids : Vec<usize> = self.active_players.keys().copied().collect();
+        player_ids.sort();
+        player_ids.extend(self.inactive_players.iter().map(|player| player.player_id));
+        return player_ids;
+    }
+
+    pub fn handling(&self, player_id: usize) -> Handling {
+        return self.handling.get(&player_id).copied().unwrap_or_else(|| Handling::new(&self.config));
+    }
+
+    pub fn set_handling(&mut self, player_id: usize, update: &HandlingUpdate) {
+        let handling = self.handling(player_id).updated(update);
+        self.handling.insert(player_id, handling);
+    }
+
+    /**
+     *
+     *  Adds an input to the back of its player's queue, or in frame
+     *  order if it is stamped and arrived out of order. Inputs which
+     *  don't press anything, duplicates and inputs older than one
+     *  already applied are dropped. Once max_queued_inputs are waiting,
+     *  new presses are dropped too, but a release takes the place of
+     *  the oldest press so that keys are never left held down.
+     *
+     */
+    fn queue_input(&mut self, input: Input) {
+        if input.is_empty() {
+            return;
+        }
+
+        let player_id = input.player_id();
+        let queue = self.input_queues.entry(player_id).or_default();
+
+        let mut index = match input.frame() {
+            Some(frame) => {
+                let applied = self.input_frames.get(&player_id).is_some_and(|last| frame <= *last);
+                if applied || queue.iter().any(|queued| queued.frame() == Some(frame)) {
+                    return;
+                }
+
+                // after every input which should come first, unstamped ones included
+                queue.iter()
+                    .rposition(|queued| queued.frame().is_none_or(|queued_frame| queued_frame < frame))
+                    .map_or(0, |index| index + 1)
+            },
+            None => queue.len(),
+        };
+
+        if queue.len() >= self.config.max_queued_inputs {
+            let oldest_press = if input.is_release() {
+                queue.iter().position(|queued| !queued.is_release())
+            } else {
+                None
+            };
+
+            match oldest_press {
+                Some(press) => {
+                    queue.remove(press);
+                    if press < index {
+                        index -= 1;
+                    }
+                },
+                None => return,
+            }
+        }
+
+        queue.insert(index, input);
+    }
+
+    /**
+     *
+     *  Applies up to max_inputs_per_tick of each player's queued
+     *  inputs, in order, players taking turns by id.
+     *
+     */
+    fn apply_inputs(&mut self, now: u128) {
+        let mut player_ids : Vec<usize> = self.input_queues.keys().copied().collect();
+        player_ids.sort_unstable();
+
+        for player_id in player_ids {
+            for _ in 0..self.config.max_inputs_per_tick {
+                let input = match self.input_queues.get_mut(&player_id).and_then(|queue| queue.pop_front()) {
+                    Some(input) => input,
+                    None => break,
+                };
+
+                if let Some(frame) = input.frame() {
+                    self.input_frames.insert(player_id, frame);
+                }
+
+                match input {
+                    Input::Keys(key_state) => {
+                        if update_state(&mut self.active_players, &key_state, &self.fallen_blocks, &self.config, now) {
+                            self.score.stats.record_action(player_id, now);
+                        }
+                    },
+                    Input::Key(event) => self.key_event(&event, now),
+                }
+            }
+        }
+
+        self.input_queues.retain(|_, queue| !queue.is_empty());
+    }
+
+    /**
+     *
+     *  Applies a key going down or up. Most keys act once when they are
+     *  pressed, the rest of the work for held keys is in auto_repeat.
+     *
+     */
+    fn key_event(&mut self, event: &KeyEvent, now: u128) {
+        let player_id = event.player_id;
+        let handling = self.handling(player_id);
+        let held = self.held_keys.entry(player_id).or_default();
+
+        if let Some(key_state) = held.key_event(event, &handling, now) {
+            update_state(&mut self.active_players, &key_state, &self.fallen_blocks, &self.config, now);
+        }
+
+        if event.pressed && self.active_players.contains_key(&player_id) {
+            self.score.stats.record_action(player_id, now);
+        }
+
+        // letting go of fast drop goes back to falling at the usual speed
+        if event.key == Key::FastDrop && !event.pressed {
+            let shift_period = get_shift_period(&self.score.lines, &self.config);
+            if let Some(piece) = self.active_players.get_mut(&player_id) {
+                if piece.fast_drop {
+                    piece.fast_drop = false;
+                    piece.next_shift_time = Some(now + shift_period as u128);
+                }
+            }
+        }
+    }
+
+    /**
+     *
+     *  Repeats the keys each player is holding down: shifts pieces
+     *  sideways as their DAS and ARR allow, and keeps pieces fast
+     *  dropping while fast drop is held, except while they rest on the
+     *  stack so that they still get time to move before locking.
+     *
+     */
+    fn auto_repeat(&mut self, now: u128) {
+        let mut player_ids : Vec<usize> = self.held_keys.keys().copied().collect();
+        player_ids.sort_unstable();
+
+        for player_id in player_ids {
+            let handling = self.handling(player_id);
+            let held = self.held_keys.get_mut(&player_id).unwrap();
+
+            // the timer keeps running between pieces, so shifts don't pile up
+            let shifts = held.auto_shift(&handling, now);
+            let fast_drop = held.fast_drop;
+
+            if !self.active_players.contains_key(&player_id) {
+                continue;
+            }
+
+            if let Some((key, count)) = shifts {
+                let input = KeyEvent { key, pressed: true, player_id, frame: None }.key_state();
+                for _ in 0..count.min(self.config.board.width as u32) {
+                    let before = self.active_players[&player_id].pivot;
+                    update_state(&mut self.active_players, &input, &self.fallen_blocks, &self.config, now);
+                    if self.active_players[&player_id].pivot == before {
+                        break;
+                    }
+                }
+            }
+
+            let piece = self.active_players.get_mut(&player_id).unwrap();
+            let mut below = *piece;
+            below.pivot.y += 1;
+            if fast_drop && !piece.fast_drop && !fallen_blocks_collision(&below, &self.fallen_blocks, &self.config.board) {
+                let next_shift_time = now + handling.soft_drop_millis as u128;
+                piece.fast_drop = true;
+                piece.fast_drop_millis = handling.soft_drop_millis;
+                piece.next_shift_time = Some(piece.next_shift_time.map_or(next_shift_time, |time| time.min(next_shift_time)));
+            }
+        }
+    }
+
+    /**
+     *
+     *  Clears the board for a new game. The new game gets its own seed,
+     *  drawn from the last one so that a whole session can still be
+     *  replayed from the first seed.
+     *
+     *  Everyone who was playing waits for a piece in the new game, those
+     *  whose pieces were in play first.
+     *
+     */
+    fn reset(&mut self) {
+        self.inactive_players = self.player_ids().into_iter().map(PieceState::new).collect();
+        self.active_players.clear();
+        self.fallen_blocks.clear();
+        self.input_queues.clear();
+        self.held_keys.clear();
+        self.score.reset();
+
+        self.seed = self.piece_queue.next_seed();
+        self.piece_queue = PieceQueue::new(self.config.randomizer, self.seed);
+    }
+}
+
+/**
+ *
+ *  A game, and everything which can be done to it. The server drives
+ *  its rooms through this, and so can anything else which wants to
+ *  play by the same rules, e.g. bots and tools.
+ *
+ */
+pub struct Game {
+    state: GameState,
+}
+
+impl Game {
+    pub fn new(config: Config, seed: u64) -> Game {
+        Game {
+            state: GameState::new(config, seed),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        return &self.state.config;
+    }
+
+    // the seed of the current game's piece generator
+    pub fn seed(&self) -> u64 {
+        return self.state.seed;
+    }
+
+    // the whole state of the game, for anything the rest of Game doesn't cover
+    pub fn state(&self) -> &GameState {
+        return &self.state;
+    }
+
+    pub fn add_player(&mut self, player_id: usize) {
+        self.state.add_player(player_id);
+    }
+
+    pub fn remove_player(&mut self, player_id: usize) {
+        self.state.remove_player(player_id);
+    }
+
+    pub fn player_ids(&self) -> Vec<usize> {
+        return self.state.player_ids();
+    }
+
+    // abandons the current game and starts a new one, see GameState::reset
+    pub fn reset(&mut self) {
+        self.state.reset();
+    }
+
+    // whether the player has a piece in play or is waiting for one
+    pub fn is_playing(&self, player_id: usize) -> bool {
+        return self.player_ids().contains(&player_id);
+    }
+
+    // changes how a player's held keys repeat
+    pub fn set_handling(&mut self, player_id: usize, update: &HandlingUpdate) {
+        self.state.set_handling(player_id, update);
+    }
+
+    // advances the game by one tick, see tick
+    pub fn tick(&mut self, inputs: Vec<Input>) -> Events {
+        return tick(&mut self.state, inputs);
+    }
+
+    // what the clients should be told about the game, with the events since the last snapshot
+    pub fn snapshot(&self, events: Vec<GameEvent>) -> GameSnapshot {
+        return GameSnapshot::new(&self.state, events);
+    }
+}
+
+/**
+ *
+ *  Advances the game by one fixed step of config.frame_millis: applies
+ *  the players' queued inputs, moves the pieces, locks them and clears lines,
+ *  and ends the game if the board has filled up.
+ *
+ */
+pub fn tick(state: &mut GameState, inputs: Vec<Input>) -> Events {
+    state.ticks += 1;
+    let now = state.now();
+
+    let mut events = Events::default();
+
+    // inputs are queued first so that they are applied in order, and
+    // never more than max_inputs_per_tick at once
+    for input in inputs {
+        state.queue_input(input);
+    }
+    state.apply_inputs(now);
+    state.auto_repeat(now);
+
+    // check to make sure shift works, locking pieces and clearing lines
+    shift_pieces(state, &mut events.game_events);
+
+    // If a block locked out of sight or the next piece has nowhere to spawn, end the game
+    if state.config.board.is_game_over(&state.fallen_blocks, state.piece_queue.peek_piece()) {
+        events.game_over = Some(GameOver {
+            score: state.score.points,
+            stats: state.score.stats.summary(now),
+        });
+        state.reset();
+    }
+
+    return events;
+}
+
+/**
+ *
+ *  Function which removes a given player from the player slab.
+ *  This removes the player from the entire game, not just the
+ *  board.
+ *
+ */
+#[allow(clippy::single_match)]
+fn remove_player(player_id: usize,
+                 active_players: &mut ActivePlayersType,
+                 inactive_players: &mut InactivePlayersType) {
+
+    // remove player from active_players
+    match active_players.remove(&player_id) {
+        None => println!("{} wasn't in active_players", player_id),
+        Some(_) => {},
+    };
+
+    // remove player from inactive_players
+    let mut inactive_remove_index = None;
+    for (index, inactive_player) in inactive_players.iter_mut().enumerate() {
+        if inactive_player.player_id == player_id {
+            inactive_remove_index = Some(index);
+        }
+    }
+    match inactive_remove_index {
+        // use .unwrap() because we are certain that a piece with inactive_remove_index
+        // is in inactive_players
+        Some(index) => { inactive_players.remove(index).unwrap(); },
+        None => { println!("{} wasn't in inactive_players", player_id); },
+    };
+}
+
+/**
+ *
+ *  Removes a player from the active queue and puts their piece in the inactive queue.
+ *
+ */
+fn move_to_inactive(player_id : usize,
+                    active_players: &mut ActivePlayersType,
+                    inactive_players: &mut InactivePlayersType) {
+
+    let player = active_players.remove(&player_id).unwrap();
+    inactive_players.push_back(player);
+}
+
+fn add_fallen_blocks(piece : &PieceState, fallen_blocks : &mut FallenBlocksType) {
+    let this_shape = get_shape(piece.shape);
+    let width = if this_shape.len() == 9 {3} else {4};
+    let this_origin = piece.pivot;
+
+    // iterate through all of the blocks that make up the
+    // current piece and add them to fallen_blocks.
+    for y in 0..width {
+        for x in 0..width {
+            let abs_x = x + this_origin.x;
+            let abs_y = y + this_origin.y;
+
+            if read_block(this_shape, x, y, piece.rotation) {
+                let pivot = Pivot {
+                    x: abs_x,
+                    y: abs_y,
+                };
+
+                fallen_blocks.insert(pivot, FallenBlock {
+                    shape: piece.shape,
+                    player_id: piece.player_id,
+                });
+            }
+        }
+    }
+}
+
+/**
+ *
+ *  Freezes a piece into the fallen blocks, clears any lines it
+ *  completed and scores it, crediting the players involved.
+ *
+ */
+pub fn lock_piece(piece : &PieceState,
+              fallen_blocks : &mut FallenBlocksType,
+              config : &Config,
+              score : &mut Score,
+              events : &mut Vec<GameEvent>,
+              now : u128) {
+
+    let board = &config.board;
+
+    // T-spins depend on the corners around the piece before it locks
+    let t_spin = detect_t_spin(piece, fallen_blocks, board);
+
+    add_fallen_blocks(piece, fallen_blocks);
+    let cleared_rows = clear_lines(fallen_blocks, board);
+    let lines_cleared = cleared_rows.len() as u32;
+
+    let points = score.score_lock(piece.player_id, t_spin, lines_cleared, fallen_blocks.is_empty(), events);
+    score.stats.record_lock(piece.player_id, piece.hard_drop, points, &cleared_rows, now);
+}
+
+// move piece down by 1 square
+// returns true if the player is no longer active
+#[allow(clippy::explicit_auto_deref)]
+fn drop_piece(player_id : usize,
+              fallen_blocks : &mut FallenBlocksType,
+              config : &Config,
+              active_players : &mut ActivePlayersType,
+              score : &mut Score,
+              events : &mut Vec<GameEvent>,
+              now : u128) -> bool {
+    // make a copy which we shift down and check for collision
+    let mut player_copy = *active_players.get(&player_id).unwrap();
+
+    player_copy.pivot.y += 1;
+
+    // If piece has fallen off of the screen, remove it from play
+    if fallen_blocks_collision(&player_copy, fallen_blocks, &config.board) {
+        let player : &mut PieceState = active_players.get_mut(&player_id).unwrap();
+        lock_piece(player, fallen_blocks, config, score, events, now);
+        (*player).next_shift_time = None;
+        return true;
+    }
+
+    // if there is another piece blocking the way, don't shift down yet
+    // and stop fast drop, unless it can be pushed out of the way
+    if player_collision(&player_copy, active_players, config.collision) &&
+            !(config.collision == CollisionPolicy::Push &&
+              push_pieces(&player_copy, fallen_blocks, config, active_players, now)) {
+        let deadlocked = is_deadlocked(player_id, active_players);
+        let player = active_players.get_mut(&player_id).unwrap();
+        (*player).fast_drop = false;
+
+        // a piece waiting for one which will fall or lock in time just waits
+        if !deadlocked {
+            player.blocked_since = None;
+            return false;
+        }
+
+        // but pieces which hold each other up would wait forever, so
+        // after a while the piece locks where it is
+        let blocked_since = *player.blocked_since.get_or_insert(now);
+        if now - blocked_since >= config.deadlock_millis as u128 {
+            lock_piece(player, fallen_blocks, config, score, events, now);
+            (*player).next_shift_time = None;
+            return true;
+        }
+        return false;
+    }
+
+    // if we've reached this point, the piece has not touching the edge of the screen
+    // and there is no other player in the way
+    let player : &mut PieceState = active_players.get_mut(&player_id).unwrap();
+    (*player).pivot.y += 1; // move the piece down by 1
+    (*player).last_kick = None; // falling means the last move wasn't a rotation
+    (*player).blocked_since = None;
+    track_lowest_row(player);
+    player_copy.pivot.y += 1;
+
+    // if piece is about to freeze, setup next_shift_time so that we can
+    // allow the player longer to move around when their piece is almost about to collide
+    if fallen_blocks_collision(&player_copy, fallen_blocks, &config.board) {
+        (*player).fast_drop = false; // cancel fast drop when we hit the bottom
+        start_lock_delay(player, config, now);
+    }
+    // if we are doing fast drop, there is no extra time added when we're about to
+    // hit the bottom
+    else if (*player).fast_drop {
+        (*player).next_shift_time = Some(now + (*player).fast_drop_millis as u128);
+        return false;
+    }
+    // if the player is not about to be off the screen, just do regular dropping
+    else {
+        let shift_period = get_shift_period(&score.lines, config);
+        (*player).next_shift_time = Some(now + shift_period as u128);
+    }
+
+    return false;
+}
+
+/**
+ *
+ *  Whether a piece is held up by pieces which are, directly or through
+ *  others, held up by it, so that none of them can ever fall. A piece
+ *  held up by ones which rest on the stack isn't, as they will lock.
+ *
+ */
+fn is_deadlocked(player_id : usize, active_players : &ActivePlayersType) -> bool {
+    let mut seen = vec![player_id];
+    let mut to_visit = vec![player_id];
+
+    while let Some(holder_id) = to_visit.pop() {
+        let mut below = active_players[&holder_id];
+        below.pivot.y += 1;
+
+        for other in active_players.values() {
+            if other.player_id == holder_id || !pieces_overlap(&below, other) {
+                continue;
+            }
+            if other.player_id == player_id {
+                return true;
+            }
+            if !seen.contains(&other.player_id) {
+                seen.push(other.player_id);
+                to_visit.push(other.player_id);
+            }
+        }
+    }
+    return false;
+}
+
+/**
+ *
+ *  Under CollisionPolicy::Push, moves every piece which pusher (already
+ *  shifted down) overlaps down by 1 square, to make room for it. Pieces
+ *  are only pushed if all of them can move, and a piece can't push one
+ *  which is itself resting on the stack or on another piece.
+ *
+ *  Returns true if the pieces were pushed.
+ *
+ */
+fn push_pieces(pusher : &PieceState,
+               fallen_blocks : &FallenBlocksType,
+               config : &Config,
+               active_players : &mut ActivePlayersType,
+               now : u128) -> bool {
+    let mut pushed_ids : Vec<usize> = active_players.values()
+        .filter(|piece| piece.player_id != pusher.player_id && pieces_overlap(pusher, piece))
+        .map(|piece| piece.player_id)
+        .collect();
+    pushed_ids.sort();
+
+    let mut pushed = vec![];
+    for player_id in pushed_ids.iter() {
+        let mut piece = active_players[player_id];
+        piece.pivot.y += 1;
+        if fallen_blocks_collision(&piece, fallen_blocks, &config.board) ||
+                player_collision(&piece, active_players, CollisionPolicy::Solid) {
+            return false;
+        }
+        pushed.push(piece);
+    }
+
+    for mut piece in pushed {
+        piece.last_kick = None;
+        piece.blocked_since = None;
+        track_lowest_row(&mut piece);
+        if is_grounded(&piece, fallen_blocks, &config.board) {
+            start_lock_delay(&mut piece, config, now);
+        }
+        active_players.insert(piece.player_id, piece);
+    }
+    return true;
+}
+
+/**
+ *
+ *  Under CollisionPolicy::PassThrough, a piece can lock into cells
+ *  which another player's piece is still in. Lifts every piece which
+ *  overlaps the stack straight up until it's clear, so that pieces
+ *  never lock on top of each other. A piece which would have to leave
+ *  the top of the board goes back to wait for its turn instead.
+ *
+ *  The pieces in locked have locked this tick, but are still active
+ *  until the end of it, and are left alone.
+ *
+ */
+fn lift_pieces(locked : &[usize],
+               fallen_blocks : &FallenBlocksType,
+               config : &Config,
+               active_players : &mut ActivePlayersType,
+               inactive_players : &mut InactivePlayersType,
+               now : u128) {
+    let mut player_ids : Vec<usize> = active_players.values()
+        .filter(|piece| !locked.contains(&piece.player_id) && fallen_blocks_collision(piece, fallen_blocks, &config.board))
+        .map(|piece| piece.player_id)
+        .collect();
+    player_ids.sort();
+
+    for player_id in player_ids {
+        let mut piece = active_players[&player_id];
+        while fallen_blocks_collision(&piece, fallen_blocks, &config.board) {
+            piece.pivot.y -= 1;
+        }
+
+        if screen_collision(&piece, &config.board) == CollisionType::Ceiling {
+            move_to_inactive(player_id, active_players, inactive_players);
+            continue;
+        }
+
+        piece.last_kick = None;
+        piece.blocked_since = None;
+        if is_grounded(&piece, fallen_blocks, &config.board) {
+            start_lock_delay(&mut piece, config, now);
+        }
+        active_players.insert(player_id, piece);
+    }
+}
+
+/**
+ *
+ *  Hard drops a piece: moves it straight down to where it lands and
+ *  locks it there in the same tick, scoring drop points for every row
+ *  it fell. A piece with another player's piece in the way lands on
+ *  top of that piece and locks there, just as it would on the stack,
+ *  without pushing it even under CollisionPolicy::Push.
+ *
+ */
+fn hard_drop_piece(player_id : usize,
+                   fallen_blocks : &mut FallenBlocksType,
+                   config : &Config,
+                   active_players : &mut ActivePlayersType,
+                   score : &mut Score,
+                   events : &mut Vec<GameEvent>,
+                   now : u128) {
+    let landing = landing_position(&active_players[&player_id], active_players, fallen_blocks,
+                                   &config.board, config.collision);
+
+    let player : &mut PieceState = active_players.get_mut(&player_id).unwrap();
+    let rows = (landing.y - player.pivot.y) as u32;
+
+    // falling means the last move wasn't a rotation, so a T-spin
+    // only counts if the piece was already resting on the stack
+    if rows > 0 {
+        player.last_kick = None;
+    }
+    player.pivot = landing;
+
+    score.score_hard_drop(player_id, rows, now);
+    lock_piece(player, fallen_blocks, config, score, events, now);
+    player.next_shift_time = None;
+}
+
+/**
+ *
+ *  Drops every piece whose time has come, and spawns a new piece
+ *  once per shift period.
+ *
+ */
+fn shift_pieces(state : &mut GameState, events : &mut Vec<GameEvent>) {
+    let current_time = state.now();
+    let GameState {
+        config,
+        active_players,
+        inactive_players,
+        fallen_blocks,
+        score,
+        piece_queue,
+        last_spawn_time,
+        ..
+    } = state;
+
+    // calculate shift period from score
+    let shift_period = get_shift_period(&score.lines, config);
+
+    // convert to i128 before subtracting so that negative result doesn't cause panic
+    let spawn_ready = (current_time as i128 - *last_spawn_time as i128) as f32 > shift_period;
+
+    let mut player_ids_to_drop : Vec<usize> = vec![];
+
+    for player in active_players.values() {
+        match player.next_shift_time {
+            Some(next_shift_time) => {
+                if current_time > next_shift_time {
+                    player_ids_to_drop.push(player.player_id);
+                }
+            },
+            None => panic!("There should be a next_shift_time field."),
+        };
+    }
+
+    // lowest piece first, so that a piece resting on one which is about
+    // to fall isn't held up by it
+    player_ids_to_drop.sort_by_key(|player_id| (Reverse(bottom_row(&active_players[player_id])), *player_id));
+
+    // swap pieces into and out of hold before they drop
+    hold_pieces(active_players, fallen_blocks, config, piece_queue, &shift_period, current_time);
+
+    let mut player_ids_to_remove : Vec<usize> = vec![];
+
+    // hard drops lock before anything else falls, lowest piece first so
+    // that a piece dropped onto another one which is also being hard
+    // dropped lands on top of where that one locks
+    let mut hard_drops : Vec<&PieceState> = active_players.values()
+        .filter(|player| player.hard_drop)
+        .collect();
+    hard_drops.sort_by_key(|player| (Reverse(bottom_row(player)), player.player_id));
+    let hard_drops : Vec<usize> = hard_drops.iter().map(|player| player.player_id).collect();
+
+    for player_id in hard_drops {
+        // a piece lifted off the top of the board by an earlier hard drop
+        // has already gone back to wait
+        if !active_players.contains_key(&player_id) {
+            continue;
+        }
+        hard_drop_piece(player_id, fallen_blocks, config, active_players, score, events, current_time);
+        player_ids_to_remove.push(player_id);
+        lift_pieces(&player_ids_to_remove, fallen_blocks, config, active_players, inactive_players, current_time);
+    }
+    player_ids_to_drop.retain(|player_id| !player_ids_to_remove.contains(player_id));
+
+    // actually remove players from the board
+    for player_id in player_ids_to_drop {
+        // a piece lifted off the top of the board has already gone back
+        // to wait, and one lifted onto the stack gets its lock delay first
+        let due = active_players.get(&player_id)
+            .is_some_and(|player| player.next_shift_time.is_some_and(|next_shift_time| current_time > next_shift_time));
+        if !due {
+            continue;
+        }
+        if drop_piece(player_id, fallen_blocks, config, active_players, score, events, current_time) {
+            player_ids_to_remove.push(player_id);
+            lift_pieces(&player_ids_to_remove, fallen_blocks, config, active_players, inactive_players, current_time);
+        }
+    }
+
+    // actually remove players from the board
+    for player_id in player_ids_to_remove {
+        move_to_inactive(player_id, active_players, inactive_players);
+    }
+
+    if spawn_ready {
+        // actives a single piece
+        activate_piece(active_players, inactive_players, fallen_blocks, config, piece_queue, &shift_period, current_time);
+
+        *last_spawn_time = current_time;
+    }
+}
+
+/**
+ *
+ *  Swaps every active piece whose player asked to hold with the shape
+ *  in that player's hold slot, or with the next piece from the queue
+ *  if the slot is empty. A piece can only be held once, and the new
+ *  piece starts again from the top of the board.
+ *
+ */
+pub fn hold_pieces(active_players : &mut ActivePlayersType,
+               fallen_blocks : &FallenBlocksType,
+               config : &Config,
+               piece_queue : &mut PieceQueue,
+               shift_period : &f32,
+               now : u128) {
+
+    let player_ids : Vec<usize> = active_players.values()
+        .filter(|player| player.hold_requested)
+        .map(|player| player.player_id)
+        .collect();
+
+    for player_id in player_ids {
+        let player = active_players.get_mut(&player_id).unwrap();
+        player.hold_requested = false;
+        if player.hold_used {
+            continue;
+        }
+
+        let mut new_state = *player;
+        new_state.shape = match player.held_shape {
+            Some(shape) => shape,
+            None => piece_queue.peek_piece(),
+        };
+        new_state.held_shape = Some(player.shape);
+        new_state.hold_used = true;
+        new_state.last_kick = None;
+        new_state.rotation = 0;
+        new_state.pivot.y = config.board.spawn_y();
+        new_state.lowest_row = new_state.pivot.y;
+        new_state.lock_resets = 0;
+        new_state.blocked_since = None;
+        new_state.fast_drop = false;
+        new_state.hard_drop = false;
+        new_state.next_shift_time = Some(now + (*shift_period as u128));
+
+        // if the new piece doesn't fit, the hold doesn't happen
+        if collision(&new_state, active_players, fallen_blocks, &config.board, config.collision) {
+            continue;
+        }
+
+        // only take from the queue once we know the hold will happen
+        if active_players[&player_id].held_shape.is_none() {
+            piece_queue.next_piece();
+        }
+
+        active_players.insert(player_id, new_state);
+    }
+}
+
+/**
+ *
+ *  Activates exactly one piece, if there is a player waiting and the
+ *  board has room for another piece. The piece spawns in the first
+ *  free lane, starting from a different lane each time, and waits if
+ *  every lane has another player's piece in the way.
+ *
+ */
+#[allow(clippy::single_match)]
+pub fn activate_piece(active_players : &mut ActivePlayersType,
+                  inactive_players : &mut InactivePlayersType,
+                  fallen_blocks : &FallenBlocksType,
+                  config : &Config,
+                  piece_queue : &mut PieceQueue,
+                  shift_period : & f32,
+                  now : u128) {
+
+    let num_players = active_players.len() + inactive_players.len();
+    let max_active = config.board.max_active(num_players).min(config.max_active.unwrap_or(usize::MAX));
+    if active_players.len() >= max_active || inactive_players.is_empty() {
+        return;
+    }
+
+    let mut player = *inactive_players.front().unwrap();
+    player.rotation = 0; // reset the rotation
+    player.shape = piece_queue.peek_piece(); // update the player's piece type
+
+    // find a lane where the new piece won't overlap anyone else's, or the stack
+    let lanes = config.board.spawn_positions();
+    let first_lane = piece_queue.pieces_dealt() % lanes.len();
+    let free_lane = (0..lanes.len())
+        .map(|i| lanes[(first_lane + i) % lanes.len()])
+        .find(|lane| {
+            let mut spawned = player;
+            spawned.pivot = *lane;
+            return !player_collision(&spawned, active_players, CollisionPolicy::Solid) &&
+                !fallen_blocks_collision(&spawned, fallen_blocks, &config.board);
+        });
+
+    let lane = match free_lane {
+        Some(lane) => lane,
+        None => return,
+    };
+
+    inactive_players.pop_front();
+    piece_queue.next_piece();
+    player.pivot = lane;
+
+    // we lose a bit of precision on shift_period
+    player.next_shift_time = Some(now + (*shift_period as u128));
+
+    // piece are NOT fast dropping by default
+    player.fast_drop = false;
+    player.hard_drop = false;
+
+    // every new piece may be held once
+    player.hold_used = false;
+    player.hold_requested = false;
+    player.last_kick = None;
+
+    // a new piece gets all of its lock delay resets
+    player.lowest_row = player.pivot.y;
+    player.lock_resets = 0;
+    player.blocked_since = None;
+
+    // make sure that we didn't insert a duplicate into the set
+    match active_players.insert(player.player_id, player) {
+        Some(_) => { panic!("Already a player with id {} in active_players set.", player.player_id); },
+        None => {},
+    }
+}
+
+fn get_shift_period(lines : &u32, config : &Config) -> f32 {
+    // fraction from 0 to 1 indicates where we are between 0 and speed_capped_lines lines cleared
+    let mut frac = *lines as f32 / config.speed_capped_lines as f32;
+    if frac > 1.0 {
+        frac = 1.0;
+    }
+
+    let shift_period = config.max_shift_period - ((config.max_shift_period - config.min_shift_period) * frac);
+    return shift_period;
+}
@@ -0,0 +1,161 @@
+use std::ops::RangeInclusive;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::input::{Key, KeyEvent, KeyState};
+
+// limits on the handling a player can ask for
+pub const DAS_RANGE : RangeInclusive<u64> = 0..=1000;
+pub const ARR_RANGE : RangeInclusive<u64> = 0..=500;
+pub const SOFT_DROP_RANGE : RangeInclusive<u64> = 1..=1000;
+
+/**
+ *
+ *  How a player's held keys repeat. Holding left or right shifts the
+ *  piece once, then again after das_millis (delayed auto-shift), then
+ *  every arr_millis (auto-repeat rate), or straight to the wall if
+ *  arr_millis is 0. Holding fast drop moves the piece down every
+ *  soft_drop_millis.
+ *
+ */
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Handling {
+    pub das_millis: u64,
+    pub arr_millis: u64,
+    pub soft_drop_millis: u64,
+}
+
+impl Handling {
+    // the handling for players who haven't asked for their own
+    pub fn new(config: &Config) -> Handling {
+        Handling {
+            das_millis: config.das_millis,
+            arr_millis: config.arr_millis,
+            soft_drop_millis: config.fast_drop_shift_millis,
+        }
+    }
+
+    // this handling with the settings a player asked for, kept in range
+    pub fn updated(&self, update: &HandlingUpdate) -> Handling {
+        let clamp = |value: Option<u64>, current: u64, range: RangeInclusive<u64>| {
+            return value.unwrap_or(current).clamp(*range.start(), *range.end());
+        };
+
+        Handling {
+            das_millis: clamp(update.das_millis, self.das_millis, DAS_RANGE),
+            arr_millis: clamp(update.arr_millis, self.arr_millis, ARR_RANGE),
+            soft_drop_millis: clamp(update.soft_drop_millis, self.soft_drop_millis, SOFT_DROP_RANGE),
+        }
+    }
+}
+
+/**
+ *
+ *  The handling settings a player asked to change, any left out keep
+ *  their current value.
+ *
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct HandlingUpdate {
+    pub das_millis: Option<u64>,
+    pub arr_millis: Option<u64>,
+    pub soft_drop_millis: Option<u64>,
+}
+
+/**
+ *
+ *  The keys a player is holding down, and when their piece is next
+ *  due to auto-shift.
+ *
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HeldKeys {
+    left: bool,
+    right: bool,
+
+    // the direction the piece auto-shifts in, whichever was pressed last
+    direction: Option<Key>,
+
+    // when the next auto-shift is due
+    next_shift: u128,
+
+    pub fast_drop: bool,
+}
+
+impl HeldKeys {
+    /**
+     *
+     *  Records a key going down or up. Returns the input to apply
+     *  straight away, which is pressing the key once for every key but
+     *  fast drop (which repeats from the first frame instead).
+     *
+     */
+    pub fn key_event(&mut self, event: &KeyEvent, handling: &Handling, now: u128) -> Option<KeyState> {
+        match (event.key, event.pressed) {
+            (Key::Left, true) | (Key::Right, true) => {
+                self.set_held(event.key, true);
+                self.direction = Some(event.key);
+                self.next_shift = now + handling.das_millis as u128;
+                return Some(event.key_state());
+            },
+            (Key::Left, false) | (Key::Right, false) => {
+                self.set_held(event.key, false);
+
+                // carry on in the other direction if it's still held, after a fresh delay
+                if self.direction == Some(event.key) {
+                    let other = if event.key == Key::Left { Key::Right } else { Key::Left };
+                    self.direction = if self.is_held(other) { Some(other) } else { None };
+                    self.next_shift = now + handling.das_millis as u128;
+                }
+                return None;
+            },
+            (Key::FastDrop, pressed) => {
+                self.fast_drop = pressed;
+                return None;
+            },
+            (_, true) => return Some(event.key_state()),
+            (_, false) => return None,
+        }
+    }
+
+    /**
+     *
+     *  The direction to auto-shift in, and how many shifts are due by
+     *  now, if any. With an auto-repeat rate of 0 the piece shifts as
+     *  far as it can, which is returned as u32::MAX shifts.
+     *
+     */
+    pub fn auto_shift(&mut self, handling: &Handling, now: u128) -> Option<(Key, u32)> {
+        let direction = self.direction?;
+        if now < self.next_shift {
+            return None;
+        }
+
+        if handling.arr_millis == 0 {
+            return Some((direction, u32::MAX));
+        }
+
+        let arr = handling.arr_millis as u128;
+        let shifts = (now - self.next_shift) / arr + 1;
+        self.next_shift += shifts * arr;
+        return Some((direction, shifts.min(u32::MAX as u128) as u32));
+    }
+
+    fn is_held(&self, key: Key) -> bool {
+        match key {
+            Key::Left => return self.left,
+            Key::Right => return self.right,
+            _ => return false,
+        }
+    }
+
+    fn set_held(&mut self, key: Key, held: bool) {
+        match key {
+            Key::Left => self.left = held,
+            Key::Right => self.right = held,
+            _ => {},
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::handling::HandlingUpdate;
+
+#[derive(Default, Serialize, Deserialize, Debug)]
+pub struct KeyState {
+    pub left: bool,
+    pub right: bool,
+    pub rot: bool,
+    pub counter_rot: bool,
+    pub hard_drop: bool,
+    pub fast_drop: bool,
+    #[serde(default)] // older clients don't send this
+    pub hold: bool,
+    pub player_id: usize,
+    pub player_name: String,
+
+    // the client's own count of the inputs it has sent, used to put
+    // them in order and acknowledged once applied, see GameState::input_frames
+    #[serde(default)] // older clients don't send this
+    pub frame: Option<u64>,
+}
+
+impl KeyState {
+    // whether any key was pressed, which counts towards a player's APM
+    pub fn is_action(&self) -> bool {
+        return self.left || self.right || self.rot || self.counter_rot ||
+            self.hard_drop || self.fast_drop || self.hold;
+    }
+}
+
+/**
+ *
+ *  The keys a player can hold down, named as in KeyState.
+ *
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Key {
+    Left,
+    Right,
+    Rot,
+    CounterRot,
+    HardDrop,
+    FastDrop,
+    Hold,
+}
+
+/**
+ *
+ *  A key going down or coming back up. Unlike KeyState, which moves
+ *  the piece once per message, holding a key down repeats it on the
+ *  server, see handling::HeldKeys.
+ *
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "type", rename = "key")]
+pub struct KeyEvent {
+    pub key: Key,
+    pub pressed: bool,
+
+    #[serde(default)]
+    pub player_id: usize,
+
+    // stamped like KeyState::frame
+    #[serde(default)]
+    pub frame: Option<u64>,
+}
+
+impl KeyEvent {
+    // the KeyState for pressing this key once
+    pub fn key_state(&self) -> KeyState {
+        let mut key_state = KeyState {
+            player_id: self.player_id,
+            frame: self.frame,
+            ..KeyState::default()
+        };
+
+        match self.key {
+            Key::Left => key_state.left = true,
+            Key::Right => key_state.right = true,
+            Key::Rot => key_state.rot = true,
+            Key::CounterRot => key_state.counter_rot = true,
+            Key::HardDrop => key_state.hard_drop = true,
+            Key::FastDrop => key_state.fast_drop = true,
+            Key::Hold => key_state.hold = true,
+        }
+        return key_state;
+    }
+}
+
+/**
+ *
+ *  Anything a player can send to move their piece.
+ *
+ */
+#[derive(Debug)]
+pub enum Input {
+    // every key pressed since the last message
+    Keys(KeyState),
+
+    // a single key going down or up
+    Key(KeyEvent),
+}
+
+impl Input {
+    pub fn player_id(&self) -> usize {
+        match self {
+            Input::Keys(key_state) => return key_state.player_id,
+            Input::Key(event) => return event.player_id,
+        }
+    }
+
+    pub fn frame(&self) -> Option<u64> {
+        match self {
+            Input::Keys(key_state) => return key_state.frame,
+            Input::Key(event) => return event.frame,
+        }
+    }
+
+    // whether the input does anything, releasing a key does
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Input::Keys(key_state) => return !key_state.is_action(),
+            Input::Key(_) => return false,
+        }
+    }
+
+    // whether the input lets go of a key, which must never be dropped or the key stays held
+    pub fn is_release(&self) -> bool {
+        match self {
+            Input::Keys(_) => return false,
+            Input::Key(event) => return !event.pressed,
+        }
+    }
+
+    // the server's copy of the input is labelled with the connection's player id
+    pub fn set_player_id(&mut self, player_id: usize) {
+        match self {
+            Input::Keys(key_state) => key_state.player_id = player_id,
+            Input::Key(event) => event.player_id = player_id,
+        }
+    }
+}
+
+/**
+ *
+ *  Messages from a client which aren't key presses.
+ *
+ */
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type")]
+pub enum ControlMessage {
+    // ask for a full gameState snapshot, e.g. after missing a delta
+    #[serde(rename = "resync")]
+    Resync,
+
+    // change how held keys repeat for this player
+    #[serde(rename = "handling")]
+    Handling(HandlingUpdate),
+
+    // start playing, or stop playing and just watch
+    #[serde(rename = "join")]
+    Join {
+        role: Role,
+    },
+
+    // say whether this player is ready for the next game to start
+    #[serde(rename = "ready")]
+    Ready {
+        ready: bool,
+    },
+}
+
+/**
+ *
+ *  How a connection takes part in a room's game. Spectators are sent
+ *  the game state like everyone else, but never get a piece.
+ *
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    Player,
+    Spectator,
+}
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Player => "player",
+            Role::Spectator => "spectator",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Role, String> {
+        match s {
+            "player" => Ok(Role::Player),
+            "spectator" => Ok(Role::Spectator),
+            _ => Err(format!("unknown role {:?}, expected player or spectator", s)),
+        }
+    }
+}
@@ -0,0 +1,27 @@
+// explicit returns are the house style
+#![allow(clippy::needless_return)]
+
+/*
+ * The rules of the game: pieces and their movement, piece generation,
+ * line clears, scoring, and the tick which ties them together into a
+ * Game. Nothing in here knows about networking, the server is built
+ * on top of it.
+ */
+
+pub mod board;
+pub mod clock;
+pub mod config;
+pub mod game;
+pub mod handling;
+pub mod input;
+pub mod piece_state;
+pub mod randomizer;
+pub mod scoring;
+pub mod snapshot;
+pub mod stats;
+pub mod tetris;
+mod tests;
+
+pub use crate::config::Config;
+pub use crate::game::{Game, Events, GameOver};
+pub use crate::input::{Input, KeyEvent, KeyState};
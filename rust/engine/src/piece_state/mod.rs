@@ -0,0 +1,124 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+// the pieces in play, keyed by player id
+pub type ActivePlayersType = HashMap<usize, PieceState>;
+
+// the players waiting for a turn, in order
+pub type InactivePlayersType = VecDeque<PieceState>;
+
+pub type FallenBlocksType = HashMap<Pivot, FallenBlock>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Pivot {
+    pub x: i8,
+    pub y: i8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PieceState {
+    pub shape: u8,
+    pub pivot: Pivot,
+    pub rotation: u8,
+    pub player_id: usize,
+    pub player_name: [char; 8],
+
+    // where the piece would land if it were hard dropped, only filled
+    // in for broadcasts, see tetris::landing_position
+    pub ghost: Option<Pivot>,
+
+    // the time when this piece first began touching the bottom of the screen
+    #[serde(skip)] // don't serialize this field
+    pub next_shift_time : Option<u128>,
+
+    #[serde(skip)] // don't serialize this field
+    pub fast_drop : bool,
+
+    // how long the piece takes to fall 1 square while fast dropping
+    #[serde(skip)] // don't serialize this field
+    pub fast_drop_millis : u64,
+
+    #[serde(skip)] // don't serialize this field
+    pub hard_drop : bool,
+
+    // the shape in this player's hold slot, kept between turns
+    pub held_shape : Option<u8>,
+
+    // whether the player has already held during this piece's turn
+    #[serde(skip)] // don't serialize this field
+    pub hold_used : bool,
+
+    // set by input, the hold is performed later in the same tick, see game::hold_pieces
+    #[serde(skip)] // don't serialize this field
+    pub hold_requested : bool,
+
+    // how many times the lock delay has been reset since the piece
+    // reached lowest_row, the furthest down it has been
+    #[serde(skip)] // don't serialize this field
+    pub lock_resets : u32,
+
+    #[serde(skip)] // don't serialize this field
+    pub lowest_row : i8,
+
+    // when the piece was first kept from falling by another player's
+    // piece, if it still is
+    #[serde(skip)] // don't serialize this field
+    pub blocked_since : Option<u128>,
+
+    // if the last successful move was a rotation, the kick test it used
+    #[serde(skip)] // don't serialize this field
+    pub last_kick : Option<u8>,
+}
+
+impl PieceState {
+    /**
+     *
+     *  A "null" piece for a player, this will be set properly when
+     *  the piece becomes active
+     *
+     */
+    pub fn new(player_id: usize) -> PieceState {
+        PieceState {
+            shape: 0,
+            pivot: Pivot {
+                x: 0,
+                y: 0,
+            },
+            rotation: 0,
+            player_id,
+            player_name: ['g', 'u', 'e', 's', 't', ' ', ' ', ' '],
+            ghost: None,
+            next_shift_time: None,
+            fast_drop: false,
+            fast_drop_millis: 0,
+            hard_drop: false,
+            held_shape: None,
+            hold_used: false,
+            hold_requested: false,
+            lock_resets: 0,
+            lowest_row: i8::MIN,
+            blocked_since: None,
+            last_kick: None,
+        }
+    }
+}
+
+/**
+ *
+ *  A block which has locked into place, and the player whose piece
+ *  it was part of.
+ *
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct FallenBlock {
+    pub shape: u8,
+    pub player_id: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BlockState {
+    pub original_shape: u8, // this is so we can use existing methods to determine color
+    pub position: Pivot,
+    pub player_id: usize,
+}
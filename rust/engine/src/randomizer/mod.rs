@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use rand::{Rng, SeedableRng};
+use rand::prelude::SliceRandom;
+use rand_pcg::Pcg32;
+
+// a seedable generator with a stable output, so piece sequences can be replayed
+pub type PieceRng = Pcg32;
+
+// how many upcoming pieces are shown to the players
+pub const PREVIEW_SIZE : usize = 14;
+
+const NUM_SHAPES : u8 = 7;
+
+// shape numbers used by the TGM randomizer, see tetris::get_shape
+const SHAPE_Z : u8 = 0;
+const SHAPE_S : u8 = 1;
+const SHAPE_O : u8 = 6;
+
+// TGM remembers the last 4 pieces and rolls up to this many times in all (the
+// first roll and then up to 3 rerolls) to avoid them, as the original TGM does
+const TGM_HISTORY_LEN : usize = 4;
+const TGM_ROLLS : usize = 4;
+
+/**
+ *
+ *  A strategy for picking the sequence of pieces in a game.
+ *
+ *  All randomness must come from rng so that a game can be replayed
+ *  from its seed.
+ *
+ */
+pub trait Randomizer: Send {
+    fn generate(&mut self, rng: &mut PieceRng) -> u8;
+}
+
+/**
+ *
+ *  Deals every piece once per bag of seven, in a random order.
+ *
+ */
+#[derive(Default)]
+pub struct SevenBag {
+    bag: Vec<u8>,
+}
+
+impl Randomizer for SevenBag {
+    fn generate(&mut self, rng: &mut PieceRng) -> u8 {
+        if self.bag.is_empty() {
+            self.bag = (0..NUM_SHAPES).collect();
+            self.bag.shuffle(rng);
+        }
+        return self.bag.pop().unwrap();
+    }
+}
+
+/**
+ *
+ *  Deals every piece twice per bag of fourteen, in a random order.
+ *
+ */
+#[derive(Default)]
+pub struct FourteenBag {
+    bag: Vec<u8>,
+}
+
+impl Randomizer for FourteenBag {
+    fn generate(&mut self, rng: &mut PieceRng) -> u8 {
+        if self.bag.is_empty() {
+            self.bag = (0..NUM_SHAPES).chain(0..NUM_SHAPES).collect();
+            self.bag.shuffle(rng);
+        }
+        return self.bag.pop().unwrap();
+    }
+}
+
+/**
+ *
+ *  The Tetris: The Grand Master randomizer. Picks pieces uniformly,
+ *  but rerolls a few times if the piece is one of the last four dealt.
+ *  The first piece is never an S, Z or O.
+ *
+ */
+pub struct TgmHistory {
+    history: VecDeque<u8>,
+    first: bool,
+}
+
+impl Default for TgmHistory {
+    fn default() -> TgmHistory {
+        TgmHistory {
+            // the history starts out full of S and Z pieces
+            history: vec![SHAPE_Z, SHAPE_S, SHAPE_Z, SHAPE_S].into_iter().collect(),
+            first: true,
+        }
+    }
+}
+
+impl Randomizer for TgmHistory {
+    fn generate(&mut self, rng: &mut PieceRng) -> u8 {
+        let mut piece = rng.gen_range(0, NUM_SHAPES);
+
+        if self.first {
+            while piece == SHAPE_Z || piece == SHAPE_S || piece == SHAPE_O {
+                piece = rng.gen_range(0, NUM_SHAPES);
+            }
+            self.first = false;
+        }
+        else {
+            // the first roll was above
+            for _ in 1..TGM_ROLLS {
+                if !self.history.contains(&piece) {
+                    break;
+                }
+                piece = rng.gen_range(0, NUM_SHAPES);
+            }
+        }
+
+        self.history.pop_front();
+        self.history.push_back(piece);
+        debug_assert!(self.history.len() == TGM_HISTORY_LEN);
+        return piece;
+    }
+}
+
+/**
+ *
+ *  Picks every piece uniformly at random, with no memory.
+ *
+ */
+#[derive(Default)]
+pub struct UniformRandom;
+
+impl Randomizer for UniformRandom {
+    fn generate(&mut self, rng: &mut PieceRng) -> u8 {
+        return rng.gen_range(0, NUM_SHAPES);
+    }
+}
+
+/**
+ *
+ *  The randomizers a game can be played with.
+ *
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RandomizerKind {
+    SevenBag,
+    // the original randomizer, two of every piece per bag
+    #[default]
+    FourteenBag,
+    TgmHistory,
+    Uniform,
+}
+
+impl RandomizerKind {
+    pub fn create(&self) -> Box<dyn Randomizer> {
+        match self {
+            RandomizerKind::SevenBag => Box::new(SevenBag::default()),
+            RandomizerKind::FourteenBag => Box::new(FourteenBag::default()),
+            RandomizerKind::TgmHistory => Box::new(TgmHistory::default()),
+            RandomizerKind::Uniform => Box::new(UniformRandom),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RandomizerKind::SevenBag => "7bag",
+            RandomizerKind::FourteenBag => "14bag",
+            RandomizerKind::TgmHistory => "tgm",
+            RandomizerKind::Uniform => "random",
+        }
+    }
+}
+
+impl fmt::Display for RandomizerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for RandomizerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RandomizerKind, String> {
+        match s {
+            "7bag" => Ok(RandomizerKind::SevenBag),
+            "14bag" => Ok(RandomizerKind::FourteenBag),
+            "tgm" => Ok(RandomizerKind::TgmHistory),
+            "random" => Ok(RandomizerKind::Uniform),
+            _ => Err(format!("unknown randomizer {:?}, expected one of 7bag, 14bag, tgm, random", s)),
+        }
+    }
+}
+
+/**
+ *
+ *  The pieces a game will deal, generated ahead of time so that the
+ *  upcoming pieces can be previewed whichever randomizer is used.
+ *
+ */
+pub struct PieceQueue {
+    randomizer: Box<dyn Randomizer>,
+    rng: PieceRng,
+    preview: VecDeque<u8>,
+    pieces_dealt: usize,
+}
+
+impl PieceQueue {
+    pub fn new(kind: RandomizerKind, seed: u64) -> PieceQueue {
+        let mut queue = PieceQueue {
+            randomizer: kind.create(),
+            rng: PieceRng::seed_from_u64(seed),
+            preview: VecDeque::with_capacity(PREVIEW_SIZE + 1),
+            pieces_dealt: 0,
+        };
+        queue.fill_preview();
+        return queue;
+    }
+
+    fn fill_preview(&mut self) {
+        while self.preview.len() < PREVIEW_SIZE {
+            let piece = self.randomizer.generate(&mut self.rng);
+            self.preview.push_back(piece);
+        }
+    }
+
+    /**
+     *
+     *  Deals the next piece.
+     *
+     */
+    pub fn next_piece(&mut self) -> u8 {
+        let piece = self.preview.pop_front().unwrap();
+        self.fill_preview();
+        self.pieces_dealt += 1;
+        return piece;
+    }
+
+    /*
+    Gets the next PREVIEW_SIZE pieces that will be put into play and returns them as a Vec.
+    Don't actually change the piece queue at all, hence the word "peek."
+    */
+    pub fn peek_next_pieces(&self) -> Vec<u8> {
+        return self.preview.iter().copied().collect();
+    }
+
+    // the piece which will be dealt next
+    pub fn peek_piece(&self) -> u8 {
+        return self.preview[0];
+    }
+
+    pub fn pieces_dealt(&self) -> usize {
+        return self.pieces_dealt;
+    }
+
+    /**
+     *
+     *  Draws a seed for the next game from this game's generator.
+     *
+     */
+    pub fn next_seed(&mut self) -> u64 {
+        return self.rng.gen();
+    }
+}
@@ -0,0 +1,249 @@
+use serde::{Deserialize, Serialize};
+
+use crate::stats::GameStats;
+
+/**
+ *
+ *  The kinds of T-spin, see tetris::detect_t_spin.
+ *
+ */
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum TSpin {
+    Mini,
+    Full,
+}
+
+/**
+ *
+ *  Something noteworthy which happened during a frame, sent to the
+ *  clients along with the changes to the game state.
+ *
+ */
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum GameEvent {
+    #[serde(rename_all = "camelCase")]
+    TSpin {
+        player_id: usize,
+        kind: TSpin,
+        lines: u32,
+        points: u32,
+    },
+
+    // count is the number of clears in a row after the first
+    #[serde(rename_all = "camelCase")]
+    Combo {
+        player_id: usize,
+        count: u32,
+        points: u32,
+    },
+
+    // a tetris or T-spin clear straight after another one
+    #[serde(rename_all = "camelCase")]
+    BackToBack {
+        player_id: usize,
+        points: u32,
+    },
+
+    // a clear which left the board empty
+    #[serde(rename_all = "camelCase")]
+    PerfectClear {
+        player_id: usize,
+        lines: u32,
+        points: u32,
+    },
+}
+
+/**
+ *
+ *  How many points each kind of clear is worth. Any field left out
+ *  of a scoring file keeps its default value.
+ *
+ */
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ScoringTable {
+    // clearing 1, 2, 3 or 4 lines without a T-spin
+    pub lines: [u32 ; 4],
+
+    // a T-spin clearing 0, 1, 2 or 3 lines
+    pub t_spin: [u32 ; 4],
+
+    // a mini T-spin clearing 0, 1 or 2 lines
+    pub mini_t_spin: [u32 ; 3],
+
+    // bonus per clear in a row after the first
+    pub combo: u32,
+
+    // back-to-back tetrises and T-spins are worth this percentage of the usual points
+    pub back_to_back_percent: u32,
+
+    // bonus for a perfect clear of 1, 2, 3 or 4 lines
+    pub perfect_clear: [u32 ; 4],
+
+    // for every row a piece falls when it is hard dropped
+    pub hard_drop: u32,
+}
+
+impl Default for ScoringTable {
+    fn default() -> ScoringTable {
+        ScoringTable {
+            lines: [100, 200, 300, 800],
+            t_spin: [400, 800, 1200, 1600],
+            mini_t_spin: [100, 200, 400],
+            combo: 50,
+            back_to_back_percent: 150,
+            perfect_clear: [800, 1200, 1800, 2000],
+            hard_drop: 2,
+        }
+    }
+}
+
+impl ScoringTable {
+    /**
+     *
+     *  The points for a piece locking, clearing lines_cleared lines,
+     *  possibly with a T-spin, before any bonuses.
+     *
+     */
+    pub fn lock_points(&self, t_spin: Option<TSpin>, lines_cleared: u32) -> u32 {
+        let lines = lines_cleared as usize;
+        match t_spin {
+            Some(TSpin::Full) => self.t_spin[lines.min(3)],
+            Some(TSpin::Mini) => self.mini_t_spin[lines.min(2)],
+            None if lines == 0 => 0,
+            None => self.lines[lines.min(4) - 1],
+        }
+    }
+}
+
+/**
+ *
+ *  The score of a game, along with the combo and back-to-back
+ *  streaks which earn bonus points.
+ *
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Score {
+    pub points: u32,
+
+    // how many lines have been cleared in all, which sets how fast pieces fall
+    pub lines: u32,
+
+    // how many locks in a row have cleared lines
+    clear_streak: u32,
+
+    // whether the last clear was a tetris or T-spin
+    back_to_back: bool,
+
+    // what each player has contributed
+    pub stats: GameStats,
+
+    table: ScoringTable,
+}
+
+impl Score {
+    pub fn new(table: ScoringTable) -> Score {
+        Score {
+            points: 0,
+            lines: 0,
+            clear_streak: 0,
+            back_to_back: false,
+            stats: GameStats::default(),
+            table,
+        }
+    }
+
+    // start again from nothing for a new game
+    pub fn reset(&mut self) {
+        *self = Score::new(self.table.clone());
+    }
+
+    /**
+     *
+     *  Scores a piece locking, and records any events it earned.
+     *  board_empty is whether the lock left no fallen blocks behind.
+     *
+     *  Returns the points awarded.
+     *
+     */
+    pub fn score_lock(&mut self,
+                      player_id: usize,
+                      t_spin: Option<TSpin>,
+                      lines_cleared: u32,
+                      board_empty: bool,
+                      events: &mut Vec<GameEvent>) -> u32 {
+
+        let mut points = self.table.lock_points(t_spin, lines_cleared);
+
+        if let Some(kind) = t_spin {
+            events.push(GameEvent::TSpin {
+                player_id,
+                kind,
+                lines: lines_cleared,
+                points,
+            });
+        }
+
+        // locks which don't clear anything break the combo, but not back-to-back
+        if lines_cleared == 0 {
+            self.clear_streak = 0;
+            self.points += points;
+            return points;
+        }
+
+        self.lines += lines_cleared;
+
+        let difficult = lines_cleared >= 4 || t_spin.is_some();
+        if difficult && self.back_to_back {
+            let bonus = points * self.table.back_to_back_percent / 100 - points;
+            points += bonus;
+            events.push(GameEvent::BackToBack {
+                player_id,
+                points: bonus,
+            });
+        }
+        self.back_to_back = difficult;
+
+        self.clear_streak += 1;
+        if self.clear_streak > 1 {
+            let count = self.clear_streak - 1;
+            let bonus = self.table.combo * count;
+            points += bonus;
+            events.push(GameEvent::Combo {
+                player_id,
+                count,
+                points: bonus,
+            });
+        }
+
+        if board_empty {
+            let bonus = self.table.perfect_clear[(lines_cleared as usize).min(4) - 1];
+            points += bonus;
+            events.push(GameEvent::PerfectClear {
+                player_id,
+                lines: lines_cleared,
+                points: bonus,
+            });
+        }
+
+        self.points += points;
+        return points;
+    }
+
+    /**
+     *
+     *  Scores a piece being hard dropped rows rows, and credits the
+     *  player who dropped it.
+     *
+     *  Returns the points awarded.
+     *
+     */
+    pub fn score_hard_drop(&mut self, player_id: usize, rows: u32, now: u128) -> u32 {
+        let points = self.table.hard_drop * rows;
+        self.points += points;
+        self.stats.record_points(player_id, points, now);
+        return points;
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::board::Board;
+use crate::piece_state::{PieceState, Pivot, BlockState, FallenBlocksType};
+use crate::game::GameState;
+use crate::scoring::GameEvent;
+use crate::stats::PlayerStats;
+use crate::tetris::landing_position;
+
+// bumped whenever the format of gameState or gameDelta messages changes
+pub const PROTOCOL_VERSION : u32 = 5;
+
+/**
+ *
+ *  A copy of everything clients are told about a room's game at the
+ *  end of a frame. Consecutive snapshots are compared to work out
+ *  which changes need to be broadcast.
+ *
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GameSnapshot {
+    pub fallen_blocks: FallenBlocksType,
+    pub piece_states: HashMap<usize, PieceState>,
+    pub player_queue: Vec<usize>,
+    pub piece_queue: Vec<u8>,
+    pub score: u32,
+    pub seed: u64,
+    pub board: Board,
+
+    // every player's stats, in player id order
+    pub stats: Vec<PlayerStats>,
+
+    // the frame stamp of the last input applied for each player
+    pub input_frames: HashMap<usize, u64>,
+
+    // what happened during the frame, only ever sent in a delta
+    pub events: Vec<GameEvent>,
+}
+
+/**
+ *
+ *  The changes between two snapshots. Fields which didn't change
+ *  are left empty (or None) and aren't sent to the clients.
+ *
+ */
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct GameDelta {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub added_blocks: Vec<BlockState>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed_blocks: Vec<Pivot>,
+
+    // pieces which are new or have changed since the last snapshot
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub moved_pieces: Vec<PieceState>,
+
+    // ids of the players whose pieces are no longer active
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed_pieces: Vec<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player_queue: Option<Vec<usize>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub piece_queue: Option<Vec<u8>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<u32>,
+
+    // only sent when a new game starts with a new seed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+
+    // sent when any player's counts change, with everyone's current APM
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Vec<PlayerStats>>,
+
+    // sent when any player's inputs were applied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_frames: Option<HashMap<usize, u64>>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<GameEvent>,
+}
+
+impl GameSnapshot {
+    pub fn new(game: &GameState, events: Vec<GameEvent>) -> GameSnapshot {
+        GameSnapshot {
+            fallen_blocks: game.fallen_blocks.clone(),
+            piece_states: game.active_players.iter().map(|(player_id, piece)| {
+                let mut piece = *piece;
+                piece.ghost = Some(landing_position(&piece, &game.active_players, &game.fallen_blocks,
+                                                  &game.config.board, game.config.collision));
+                return (*player_id, piece);
+            }).collect(),
+            player_queue: game.inactive_players.iter().map(|player| player.player_id).collect(),
+            // the next 14 pieces that will be deployed
+            piece_queue: game.piece_queue.peek_next_pieces(),
+            score: game.score.points,
+            seed: game.seed,
+            board: game.config.board,
+            stats: game.score.stats.summary(game.now()),
+            input_frames: game.input_frames.clone(),
+            events,
+        }
+    }
+
+    /**
+     *
+     *  Works out what has changed between prev and this snapshot.
+     *
+     */
+    pub fn diff(&self, prev: &GameSnapshot) -> GameDelta {
+        let mut delta = GameDelta::default();
+
+        for (pivot, block) in self.fallen_blocks.iter() {
+            if prev.fallen_blocks.get(pivot) != Some(block) {
+                delta.added_blocks.push(BlockState {
+                    position: *pivot,
+                    original_shape: block.shape,
+                    player_id: block.player_id,
+                });
+            }
+        }
+        for pivot in prev.fallen_blocks.keys() {
+            if !self.fallen_blocks.contains_key(pivot) {
+                delta.removed_blocks.push(*pivot);
+            }
+        }
+
+        for (player_id, piece) in self.piece_states.iter() {
+            if prev.piece_states.get(player_id) != Some(piece) {
+                delta.moved_pieces.push(*piece);
+            }
+        }
+        for player_id in prev.piece_states.keys() {
+            if !self.piece_states.contains_key(player_id) {
+                delta.removed_pieces.push(*player_id);
+            }
+        }
+
+        if self.player_queue != prev.player_queue {
+            delta.player_queue = Some(self.player_queue.clone());
+        }
+        if self.piece_queue != prev.piece_queue {
+            delta.piece_queue = Some(self.piece_queue.clone());
+        }
+        if self.score != prev.score {
+            delta.score = Some(self.score);
+        }
+        if self.seed != prev.seed {
+            delta.seed = Some(self.seed);
+        }
+
+        // the APM alone changes every frame, so it isn't worth sending a delta for
+        let stats_changed = self.stats.len() != prev.stats.len() ||
+            self.stats.iter().zip(prev.stats.iter()).any(|(stats, prev)| !stats.same_counts(prev));
+        if stats_changed {
+            delta.stats = Some(self.stats.clone());
+        }
+
+        if self.input_frames != prev.input_frames {
+            delta.input_frames = Some(self.input_frames.clone());
+        }
+
+        // events aren't state, so they are sent once, in this frame's delta
+        delta.events = self.events.clone();
+
+        return delta;
+    }
+
+    /**
+     *
+     *  The full gameState message, sent when a client joins or asks
+     *  for a resync. seq is the sequence number of the last delta
+     *  this snapshot includes.
+     *
+     */
+    pub fn to_message(&self, seq: u64) -> Value {
+        let fallen_blocks_list : Vec<BlockState> = self.fallen_blocks.iter().map(|(pivot, block)| {
+            return BlockState {
+                position: *pivot,
+                original_shape: block.shape,
+                player_id: block.player_id,
+            };
+        }).collect();
+
+        let states : Vec<&PieceState> = self.piece_states.values().collect();
+
+        return json!({
+            "type": "gameState",
+            "version": PROTOCOL_VERSION,
+            "seq": seq,
+            "piece_states": states,
+            "fallen_blocks": fallen_blocks_list,
+            "player_queue": self.player_queue,
+            "piece_queue": self.piece_queue,
+            "score": self.score,
+            "seed": self.seed,
+            "board": self.board,
+            "stats": self.stats,
+            "input_frames": self.input_frames,
+        });
+    }
+}
+
+impl GameDelta {
+    pub fn is_empty(&self) -> bool {
+        return *self == GameDelta::default();
+    }
+
+    /**
+     *
+     *  The gameDelta message. Clients apply deltas in seq order and
+     *  ask for a resync if they notice one is missing.
+     *
+     */
+    pub fn to_message(&self, seq: u64) -> Value {
+        let mut message = serde_json::to_value(self).unwrap();
+        message["type"] = json!("gameDelta");
+        message["version"] = json!(PROTOCOL_VERSION);
+        message["seq"] = json!(seq);
+        return message;
+    }
+}
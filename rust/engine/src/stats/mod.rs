@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+// APM is measured over at least this long, so that a couple of quick
+// actions at the start of a game don't read as a huge APM
+const MIN_APM_MILLIS : u128 = 10_000;
+
+const MILLIS_PER_MINUTE : f32 = 60_000.0;
+
+/**
+ *
+ *  What a single player has done during the current game.
+ *
+ */
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PlayerStats {
+    pub player_id: usize,
+    pub pieces_placed: u32,
+
+    // lines this player had at least one block in when they were cleared
+    pub lines_cleared: u32,
+
+    // 4 line clears made by this player's piece
+    pub tetrises: u32,
+    pub hard_drops: u32,
+
+    // the points awarded for this player's pieces locking
+    pub points: u32,
+
+    // inputs which pressed at least one key
+    pub actions: u32,
+
+    // actions per minute, worked out when the stats are sent
+    pub apm: f32,
+
+    // when this player's first action or piece was recorded
+    #[serde(skip)] // don't serialize this field
+    started: u128,
+}
+
+impl PlayerStats {
+    fn new(player_id: usize, now: u128) -> PlayerStats {
+        PlayerStats {
+            player_id,
+            pieces_placed: 0,
+            lines_cleared: 0,
+            tetrises: 0,
+            hard_drops: 0,
+            points: 0,
+            actions: 0,
+            apm: 0.0,
+            started: now,
+        }
+    }
+
+    // whether the counters are the same, ignoring the APM which changes over time
+    pub fn same_counts(&self, other: &PlayerStats) -> bool {
+        return PlayerStats { apm: other.apm, ..self.clone() } == *other;
+    }
+}
+
+/**
+ *
+ *  The stats of every player who has taken part in the current game,
+ *  including players who have since left.
+ *
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GameStats {
+    players: HashMap<usize, PlayerStats>,
+}
+
+impl GameStats {
+    fn player(&mut self, player_id: usize, now: u128) -> &mut PlayerStats {
+        return self.players.entry(player_id)
+            .or_insert_with(|| PlayerStats::new(player_id, now));
+    }
+
+    pub fn record_action(&mut self, player_id: usize, now: u128) {
+        self.player(player_id, now).actions += 1;
+    }
+
+    // credits a player with points earned other than by locking, e.g. for dropping
+    pub fn record_points(&mut self, player_id: usize, points: u32, now: u128) {
+        self.player(player_id, now).points += points;
+    }
+
+    /**
+     *
+     *  Credits a player's piece locking. cleared_rows holds the ids of
+     *  the players who had blocks in each line the lock cleared, each
+     *  of whom is credited with the line.
+     *
+     */
+    pub fn record_lock(&mut self,
+                       player_id: usize,
+                       hard_drop: bool,
+                       points: u32,
+                       cleared_rows: &[Vec<usize>],
+                       now: u128) {
+
+        let player = self.player(player_id, now);
+        player.pieces_placed += 1;
+        player.points += points;
+        if hard_drop {
+            player.hard_drops += 1;
+        }
+        if cleared_rows.len() >= 4 {
+            player.tetrises += 1;
+        }
+
+        for contributors in cleared_rows {
+            for contributor in contributors {
+                self.player(*contributor, now).lines_cleared += 1;
+            }
+        }
+    }
+
+    /**
+     *
+     *  Every player's stats, in player id order, with their APM
+     *  worked out as of now.
+     *
+     */
+    pub fn summary(&self, now: u128) -> Vec<PlayerStats> {
+        let mut summary : Vec<PlayerStats> = self.players.values().map(|player| {
+            let elapsed = now.saturating_sub(player.started).max(MIN_APM_MILLIS);
+            return PlayerStats {
+                apm: player.actions as f32 * MILLIS_PER_MINUTE / elapsed as f32,
+                ..player.clone()
+            };
+        }).collect();
+
+        summary.sort_by_key(|player| player.player_id);
+        return summary;
+    }
+}
@@ -0,0 +1,1224 @@
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests {
+    use crate::randomizer::{PieceQueue, RandomizerKind, PREVIEW_SIZE};
+    use crate::snapshot::GameSnapshot;
+    use crate::piece_state::{PieceState, Pivot, FallenBlock, ActivePlayersType, FallenBlocksType};
+    use crate::input::{Input, Key, KeyEvent, KeyState};
+    use crate::handling::{Handling, HandlingUpdate};
+    use crate::clock::{Clock, ManualClock, Ticker, MAX_CATCH_UP_TICKS};
+    use crate::game::{Game, GameState, tick, hold_pieces, activate_piece, lock_piece};
+    use std::collections::VecDeque;
+    use crate::tetris::{update_state, landing_position, CollisionPolicy, is_grounded, track_lowest_row, start_lock_delay, get_shape, read_block, detect_t_spin, clear_lines, fallen_blocks_collision, screen_collision, CollisionType, SHAPE_I, SHAPE_O, SHAPE_T};
+    use crate::board::Board;
+    use crate::config::{Config, Settings};
+    use crate::stats::GameStats;
+    use crate::scoring::{GameEvent, Score, ScoringTable, TSpin};
+
+    const RANDOMIZERS : [RandomizerKind ; 4] = [
+        RandomizerKind::SevenBag,
+        RandomizerKind::FourteenBag,
+        RandomizerKind::TgmHistory,
+        RandomizerKind::Uniform,
+    ];
+
+    fn piece_sequence(kind: RandomizerKind, seed: u64, length: usize) -> Vec<u8> {
+        let mut piece_queue = PieceQueue::new(kind, seed);
+        return (0..length).map(|_| piece_queue.next_piece()).collect();
+    }
+
+    #[test]
+    fn test_next_piece() {
+        for kind in RANDOMIZERS.iter() {
+            let mut piece_queue = PieceQueue::new(*kind, 0);
+            assert!(piece_queue.next_piece() <= 6);
+            assert!(piece_queue.next_piece() <= 6);
+            assert_eq!(piece_queue.pieces_dealt(), 2);
+        }
+    }
+
+    #[test]
+    fn test_seeded_piece_sequence() {
+        let length = 100;
+        for kind in RANDOMIZERS.iter() {
+            assert_eq!(piece_sequence(*kind, 42, length), piece_sequence(*kind, 42, length));
+            assert_ne!(piece_sequence(*kind, 42, length), piece_sequence(*kind, 43, length));
+        }
+    }
+
+    #[test]
+    fn test_peek_next_pieces() {
+        // the preview always matches the pieces that are dealt next
+        for kind in RANDOMIZERS.iter() {
+            let mut piece_queue = PieceQueue::new(*kind, 3);
+            for _ in 0..30 {
+                let preview = piece_queue.peek_next_pieces();
+                assert_eq!(preview.len(), PREVIEW_SIZE);
+
+                let mut copy = PieceQueue::new(*kind, 3);
+                for _ in 0..piece_queue.pieces_dealt() {
+                    copy.next_piece();
+                }
+                let dealt : Vec<u8> = (0..PREVIEW_SIZE).map(|_| copy.next_piece()).collect();
+                assert_eq!(preview, dealt);
+
+                piece_queue.next_piece();
+            }
+        }
+    }
+
+    #[test]
+    fn test_bag_randomizers() {
+        // every bag holds each piece the same number of times
+        for (kind, bag_size) in [(RandomizerKind::SevenBag, 7), (RandomizerKind::FourteenBag, 14)].iter() {
+            let sequence = piece_sequence(*kind, 7, bag_size * 5);
+            for bag in sequence.chunks(*bag_size) {
+                for shape in 0..7 {
+                    assert_eq!(bag.iter().filter(|piece| **piece == shape).count(), bag_size / 7);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_tgm_randomizer() {
+        for seed in 0..50 {
+            let sequence = piece_sequence(RandomizerKind::TgmHistory, seed, 50);
+            // the first piece is never S, Z or O
+            assert!(![0, 1, 6].contains(&sequence[0]));
+            assert!(sequence.iter().all(|piece| *piece <= 6));
+        }
+    }
+
+    fn test_piece(player_id: usize, x: i8, y: i8) -> PieceState {
+        let mut piece = PieceState::new(player_id);
+        piece.shape = 4;
+        piece.pivot = Pivot { x, y };
+        return piece;
+    }
+
+    // a fallen block placed by nobody in particular
+    fn block(shape: u8) -> FallenBlock {
+        return FallenBlock { shape, player_id: 0 };
+    }
+
+    #[test]
+    fn test_hold_piece() {
+        let mut piece_queue = PieceQueue::new(RandomizerKind::SevenBag, 5);
+        let mut active_players = ActivePlayersType::new();
+        let fallen_blocks = FallenBlocksType::new();
+
+        let mut piece = test_piece(1, 5, 6);
+        piece.rotation = 2;
+        piece.hold_requested = true;
+        active_players.insert(1, piece);
+
+        // with an empty hold slot, the next piece comes from the queue
+        let next = piece_queue.peek_piece();
+        hold_pieces(&mut active_players, &fallen_blocks, &Config::default(), &mut piece_queue, &400.0, 0);
+        let held = active_players[&1];
+        assert_eq!(held.shape, next);
+        assert_eq!(held.held_shape, Some(4));
+        assert_eq!(held.rotation, 0);
+        assert_eq!(held.pivot, Pivot { x: 5, y: Board::default().spawn_y() });
+        assert_eq!(piece_queue.pieces_dealt(), 1);
+
+        // a piece can only be held once
+        active_players.get_mut(&1).unwrap().hold_requested = true;
+        hold_pieces(&mut active_players, &fallen_blocks, &Config::default(), &mut piece_queue, &400.0, 0);
+        assert_eq!(active_players[&1].shape, next);
+        assert!(!active_players[&1].hold_requested);
+
+        // once allowed again, the held shape is swapped back in
+        active_players.get_mut(&1).unwrap().hold_used = false;
+        active_players.get_mut(&1).unwrap().hold_requested = true;
+        hold_pieces(&mut active_players, &fallen_blocks, &Config::default(), &mut piece_queue, &400.0, 0);
+        assert_eq!(active_players[&1].shape, 4);
+        assert_eq!(active_players[&1].held_shape, Some(next));
+        assert_eq!(piece_queue.pieces_dealt(), 1);
+    }
+
+    #[test]
+    fn test_hold_blocked() {
+        let mut piece_queue = PieceQueue::new(RandomizerKind::SevenBag, 5);
+        let mut active_players = ActivePlayersType::new();
+        let mut fallen_blocks = FallenBlocksType::new();
+
+        // fill the top of the board so the new piece can't spawn
+        let top = Board::default().spawn_y();
+        for x in 0..20 {
+            fallen_blocks.insert(Pivot { x, y: top }, block(0));
+            fallen_blocks.insert(Pivot { x, y: top + 1 }, block(0));
+        }
+
+        let mut piece = test_piece(1, 5, 10);
+        piece.hold_requested = true;
+        active_players.insert(1, piece);
+
+        hold_pieces(&mut active_players, &fallen_blocks, &Config::default(), &mut piece_queue, &400.0, 0);
+        assert_eq!(active_players[&1].shape, 4);
+        assert_eq!(active_players[&1].held_shape, None);
+        assert_eq!(piece_queue.pieces_dealt(), 0);
+    }
+
+    // SRS kicks in board coordinates (y down), [rotation before][clockwise ? 0 : 1]
+    const BOARD_JLSTZ_KICKS : [[[(i8, i8) ; 5] ; 2] ; 4] = [
+        [[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)], [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)]],
+        [[(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)], [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)]],
+        [[(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)], [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)]],
+        [[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)]],
+    ];
+    const BOARD_I_KICKS : [[[(i8, i8) ; 5] ; 2] ; 4] = [
+        [[(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)], [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)]],
+        [[(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)], [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)]],
+        [[(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)], [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)]],
+        [[(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)], [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)]],
+    ];
+
+    // the board cells covered by a piece
+    fn piece_cells(piece: &PieceState) -> Vec<Pivot> {
+        let shape = get_shape(piece.shape);
+        let width = if shape.len() == 9 {3} else {4};
+        let mut cells = vec![];
+        for y in 0..width {
+            for x in 0..width {
+                if read_block(shape, x, y, piece.rotation) {
+                    cells.push(Pivot { x: piece.pivot.x + x, y: piece.pivot.y + y });
+                }
+            }
+        }
+        return cells;
+    }
+
+    fn rotate(active_players: &mut ActivePlayersType, fallen_blocks: &FallenBlocksType, clockwise: bool) {
+        let input = KeyState {
+            rot: clockwise,
+            counter_rot: !clockwise,
+            player_id: 1,
+            player_name: "guest".to_string(),
+            ..KeyState::default()
+        };
+        update_state(active_players, &input, fallen_blocks, &Config::default(), 0);
+    }
+
+    /*
+    For every rotation transition of the JLSTZ and I pieces, and every kick
+    test, fill the board so that only the original position and that kick's
+    position are empty. The piece must end up at the first kick that fits.
+    */
+    #[test]
+    fn test_srs_kicks() {
+        for (shape, kicks) in [(4, BOARD_JLSTZ_KICKS), (SHAPE_I, BOARD_I_KICKS)].iter() {
+            for prev_rotation in 0..4u8 {
+                for (direction, clockwise) in [true, false].iter().enumerate() {
+                    let rotation = if *clockwise { (prev_rotation + 1) % 4 } else { (prev_rotation + 3) % 4 };
+                    let transition = kicks[prev_rotation as usize][direction];
+
+                    for kick in 0..5 {
+                        let mut piece = test_piece(1, 8, 8);
+                        piece.shape = *shape;
+                        piece.rotation = prev_rotation;
+
+                        let kicked = |test: usize| {
+                            let mut kicked = piece;
+                            kicked.rotation = rotation;
+                            kicked.pivot.x += transition[test].0;
+                            kicked.pivot.y += transition[test].1;
+                            kicked.last_kick = Some(test as u8);
+                            kicked.lowest_row = kicked.lowest_row.max(kicked.pivot.y);
+                            return kicked;
+                        };
+
+                        let mut free = piece_cells(&piece);
+                        free.extend(piece_cells(&kicked(kick)));
+
+                        let mut fallen_blocks = FallenBlocksType::new();
+                        for x in 0..20 {
+                            for y in 0..20 {
+                                if !free.contains(&Pivot { x, y }) {
+                                    fallen_blocks.insert(Pivot { x, y }, block(0));
+                                }
+                            }
+                        }
+
+                        let expected = (0..5).find(|test| {
+                            piece_cells(&kicked(*test)).iter().all(|cell| free.contains(cell))
+                        }).unwrap();
+
+                        let mut active_players = ActivePlayersType::new();
+                        active_players.insert(1, piece);
+                        rotate(&mut active_players, &fallen_blocks, *clockwise);
+
+                        // the kick may leave the piece resting on the stack, which
+                        // resets its lock delay, see test_lock_delay
+                        let mut rotated = active_players[&1];
+                        rotated.next_shift_time = None;
+                        rotated.lock_resets = 0;
+                        assert_eq!(rotated, kicked(expected),
+                                   "shape {} rotation {} -> {} kick {}", shape, prev_rotation, rotation, kick);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_srs_blocked_rotation() {
+        // a T piece in a T-shaped hole can't rotate at all
+        let piece = test_piece(1, 8, 8);
+        let free = piece_cells(&piece);
+        let mut fallen_blocks = FallenBlocksType::new();
+        for x in 0..20 {
+            for y in 0..20 {
+                if !free.contains(&Pivot { x, y }) {
+                    fallen_blocks.insert(Pivot { x, y }, block(0));
+                }
+            }
+        }
+
+        let mut active_players = ActivePlayersType::new();
+        active_players.insert(1, piece);
+        rotate(&mut active_players, &fallen_blocks, true);
+        assert_eq!(active_players[&1], piece);
+    }
+
+    #[test]
+    fn test_srs_kick_off_player() {
+        // another player's piece where the T would rotate to kicks it left
+        let piece = test_piece(1, 8, 8);
+        let mut other = test_piece(2, 8, 9);
+        other.shape = SHAPE_O;
+
+        let mut active_players = ActivePlayersType::new();
+        active_players.insert(1, piece);
+        active_players.insert(2, other);
+        rotate(&mut active_players, &FallenBlocksType::new(), true);
+
+        let mut expected = piece;
+        expected.rotation = 1;
+        expected.pivot.x -= 1;
+        expected.last_kick = Some(1);
+        expected.lowest_row = 8;
+        assert_eq!(active_players[&1], expected);
+    }
+
+    #[test]
+    fn test_o_rotation() {
+        let mut piece = test_piece(1, 8, 8);
+        piece.shape = SHAPE_O;
+
+        let mut active_players = ActivePlayersType::new();
+        active_players.insert(1, piece);
+        rotate(&mut active_players, &FallenBlocksType::new(), true);
+        rotate(&mut active_players, &FallenBlocksType::new(), false);
+        rotate(&mut active_players, &FallenBlocksType::new(), false);
+        assert_eq!(active_players[&1], piece);
+    }
+
+    #[test]
+    fn test_wall_kick() {
+        // an I piece standing against the left wall is kicked out when it rotates flat
+        let mut piece = test_piece(1, -2, 8);
+        piece.shape = SHAPE_I;
+        piece.rotation = 1;
+
+        let mut active_players = ActivePlayersType::new();
+        active_players.insert(1, piece);
+        rotate(&mut active_players, &FallenBlocksType::new(), true);
+
+        let rotated = active_players[&1];
+        assert_eq!(rotated.rotation, 2);
+        assert!(piece_cells(&rotated).iter().all(|cell| cell.x >= 0));
+    }
+
+    #[test]
+    fn test_t_spin_double() {
+        // a T pointing down into a slot under an overhang at x = 5
+        let mut fallen_blocks = FallenBlocksType::new();
+        for x in 0..20 {
+            if x != 6 {
+                fallen_blocks.insert(Pivot { x, y: 19 }, block(0));
+            }
+            if !(5..8).contains(&x) {
+                fallen_blocks.insert(Pivot { x, y: 18 }, block(0));
+            }
+        }
+        fallen_blocks.insert(Pivot { x: 5, y: 17 }, block(0));
+
+        let mut piece = test_piece(1, 5, 17);
+        piece.rotation = 2;
+        piece.last_kick = Some(1);
+        assert_eq!(detect_t_spin(&piece, &fallen_blocks, &Board::default()), Some(TSpin::Full));
+
+        // without a rotation as the last move it's an ordinary double
+        piece.last_kick = None;
+        assert_eq!(detect_t_spin(&piece, &fallen_blocks, &Board::default()), None);
+        piece.last_kick = Some(1);
+
+        let mut score = Score::new(ScoringTable::default());
+        let mut events = vec![];
+        lock_piece(&piece, &mut fallen_blocks, &Config::default(), &mut score, &mut events, 0);
+        assert_eq!(score.points, 1200);
+        assert_eq!(score.lines, 2);
+        assert_eq!(events, vec![GameEvent::TSpin { player_id: 1, kind: TSpin::Full, lines: 2, points: 1200 }]);
+        assert_eq!(fallen_blocks.len(), 1);
+    }
+
+    #[test]
+    fn test_t_spin_mini() {
+        // a T pointing up on the floor, with one of its top corners filled
+        let mut fallen_blocks = FallenBlocksType::new();
+        fallen_blocks.insert(Pivot { x: 5, y: 18 }, block(0));
+
+        let mut piece = test_piece(1, 5, 18);
+        piece.last_kick = Some(0);
+        assert_eq!(detect_t_spin(&piece, &fallen_blocks, &Board::default()), Some(TSpin::Mini));
+
+        // the last kick test always makes a full T-spin
+        piece.last_kick = Some(4);
+        assert_eq!(detect_t_spin(&piece, &fallen_blocks, &Board::default()), Some(TSpin::Full));
+
+        // with only the floor corners filled it isn't a T-spin
+        piece.last_kick = Some(0);
+        assert_eq!(detect_t_spin(&piece, &FallenBlocksType::new(), &Board::default()), None);
+
+        // other pieces never T-spin
+        piece.shape = 0;
+        assert_eq!(detect_t_spin(&piece, &fallen_blocks, &Board::default()), None);
+    }
+
+    #[test]
+    fn test_lock_points() {
+        let table = ScoringTable::default();
+        assert_eq!(table.lock_points(None, 0), 0);
+        assert_eq!(table.lock_points(None, 2), 200);
+        assert_eq!(table.lock_points(None, 4), 800);
+        assert_eq!(table.lock_points(Some(TSpin::Mini), 0), 100);
+        assert_eq!(table.lock_points(Some(TSpin::Mini), 1), 200);
+        assert_eq!(table.lock_points(Some(TSpin::Full), 0), 400);
+        assert_eq!(table.lock_points(Some(TSpin::Full), 3), 1600);
+    }
+
+    #[test]
+    fn test_combo_and_back_to_back() {
+        let mut score = Score::new(ScoringTable::default());
+        let mut events = vec![];
+
+        assert_eq!(score.score_lock(1, None, 4, false, &mut events), 800);
+        assert!(events.is_empty());
+
+        // a second tetris in a row is back-to-back and continues the combo
+        assert_eq!(score.score_lock(2, None, 4, false, &mut events), 1200 + 50);
+        assert_eq!(events, vec![
+            GameEvent::BackToBack { player_id: 2, points: 400 },
+            GameEvent::Combo { player_id: 2, count: 1, points: 50 },
+        ]);
+
+        // a lock without a clear breaks the combo but keeps back-to-back
+        events.clear();
+        assert_eq!(score.score_lock(1, None, 0, false, &mut events), 0);
+        assert_eq!(score.score_lock(1, Some(TSpin::Full), 2, false, &mut events), 1800);
+        assert_eq!(events, vec![
+            GameEvent::TSpin { player_id: 1, kind: TSpin::Full, lines: 2, points: 1200 },
+            GameEvent::BackToBack { player_id: 1, points: 600 },
+        ]);
+
+        // an ordinary clear breaks back-to-back
+        events.clear();
+        assert_eq!(score.score_lock(1, None, 1, false, &mut events), 100 + 50);
+        assert_eq!(score.score_lock(1, None, 4, false, &mut events), 800 + 100);
+        assert_eq!(score.points, 800 + 1250 + 1800 + 150 + 900);
+
+        score.reset();
+        assert_eq!(score, Score::new(ScoringTable::default()));
+    }
+
+    #[test]
+    fn test_perfect_clear() {
+        let table : ScoringTable = serde_json::from_str(r#"{"perfect_clear": [1, 2, 3, 4]}"#).unwrap();
+        assert_eq!(table.lines, ScoringTable::default().lines);
+
+        let mut score = Score::new(table);
+        let mut events = vec![];
+        assert_eq!(score.score_lock(3, None, 2, true, &mut events), 200 + 2);
+        assert_eq!(events, vec![GameEvent::PerfectClear { player_id: 3, lines: 2, points: 2 }]);
+    }
+
+    #[test]
+    fn test_rectangular_board() {
+        let board = Board { width: 10, height: 24, buffer: 4 };
+        assert_eq!(board.spawn_positions(), vec![Pivot { x: 1, y: -2 }, Pivot { x: 6, y: -2 }]);
+
+        // the floor is at the height, and pieces can move up into the buffer
+        let mut piece = test_piece(1, 0, 22);
+        assert!(screen_collision(&piece, &board) == CollisionType::None);
+        piece.pivot.y = 23;
+        assert!(screen_collision(&piece, &board) == CollisionType::Floor);
+        piece.pivot.y = -4;
+        assert!(screen_collision(&piece, &board) == CollisionType::None);
+        piece.pivot.y = -5;
+        assert!(screen_collision(&piece, &board) == CollisionType::Ceiling);
+        piece.pivot = Pivot { x: 8, y: 0 };
+        assert!(screen_collision(&piece, &board) == CollisionType::Wall);
+
+        // only the 10 columns need filling to clear the bottom line
+        let mut fallen_blocks = FallenBlocksType::new();
+        for x in 0..board.width {
+            fallen_blocks.insert(Pivot { x, y: 23 }, block(0));
+        }
+        fallen_blocks.insert(Pivot { x: 0, y: 22 }, block(1));
+        assert_eq!(clear_lines(&mut fallen_blocks, &board).len(), 1);
+        assert_eq!(fallen_blocks.len(), 1);
+        assert!(!board.is_game_over(&fallen_blocks, SHAPE_T));
+
+        // a block locked in the buffer zone ends the game
+        fallen_blocks.insert(Pivot { x: 0, y: -1 }, block(1));
+        assert!(board.is_game_over(&fallen_blocks, SHAPE_T));
+
+        // without a buffer, the stack reaching a spawn position blocks
+        // that lane, and ends the game once every lane is blocked
+        let board = Board { buffer: 0, ..board };
+        let mut fallen_blocks = FallenBlocksType::new();
+        fallen_blocks.insert(Pivot { x: 0, y: 0 }, block(1));
+        assert!(!board.is_game_over(&fallen_blocks, SHAPE_T));
+        fallen_blocks.insert(Pivot { x: 2, y: 1 }, block(1));
+        assert!(!board.is_game_over(&fallen_blocks, SHAPE_T));
+        fallen_blocks.insert(Pivot { x: 7, y: 1 }, block(1));
+        assert!(board.is_game_over(&fallen_blocks, SHAPE_T));
+    }
+
+    #[test]
+    fn test_spawn_lanes() {
+        let board = Board::default();
+        assert_eq!(board.lane_count(), 4);
+        let lanes : Vec<i8> = board.spawn_positions().iter().map(|lane| lane.x).collect();
+        assert_eq!(lanes, vec![1, 6, 11, 16]);
+
+        // the spare columns are split between the edges
+        let board = Board { width: 23, ..board };
+        let lanes : Vec<i8> = board.spawn_positions().iter().map(|lane| lane.x).collect();
+        assert_eq!(lanes, vec![2, 7, 12, 17]);
+
+        assert_eq!(board.max_active(1), 1);
+        assert_eq!(board.max_active(3), 3);
+        assert_eq!(board.max_active(8), 4);
+    }
+
+    #[test]
+    fn test_activate_piece() {
+        let config = Config { board: Board { width: 10, ..Board::default() }, ..Config::default() };
+        let mut piece_queue = PieceQueue::new(RandomizerKind::SevenBag, 5);
+        let mut active_players = ActivePlayersType::new();
+        let mut inactive_players : VecDeque<PieceState> = (1..=3).map(PieceState::new).collect();
+        let fallen_blocks = FallenBlocksType::new();
+
+        // pieces keep spawning until every lane is taken
+        for _ in 0..3 {
+            activate_piece(&mut active_players, &mut inactive_players, &fallen_blocks, &config, &mut piece_queue, &400.0, 0);
+        }
+        assert_eq!(active_players.len(), 2);
+        assert_eq!(inactive_players.len(), 1);
+        assert_ne!(active_players[&1].pivot, active_players[&2].pivot);
+
+        // a piece still sitting in a lane keeps it blocked
+        let pivot = active_players[&1].pivot;
+        active_players.remove(&2);
+        activate_piece(&mut active_players, &mut inactive_players, &fallen_blocks, &config, &mut piece_queue, &400.0, 0);
+        assert_ne!(active_players[&3].pivot, pivot);
+
+        // with an I piece lying across both lanes, the next T has to wait
+        while piece_queue.peek_piece() != 4 {
+            piece_queue.next_piece();
+        }
+        let mut blocker = test_piece(5, 3, -2);
+        blocker.shape = SHAPE_I;
+        let mut active_players = ActivePlayersType::new();
+        active_players.insert(5, blocker);
+        let mut inactive_players : VecDeque<PieceState> = vec![PieceState::new(4)].into();
+
+        activate_piece(&mut active_players, &mut inactive_players, &fallen_blocks, &config, &mut piece_queue, &400.0, 0);
+        assert_eq!(active_players.len(), 1);
+        assert_eq!(inactive_players.len(), 1);
+
+        // once it moves out of the way of one lane, the T spawns there
+        active_players.get_mut(&5).unwrap().pivot.x = 5;
+        activate_piece(&mut active_players, &mut inactive_players, &fallen_blocks, &config, &mut piece_queue, &400.0, 0);
+        assert_eq!(active_players[&4].pivot, Pivot { x: 1, y: -2 });
+    }
+
+    #[test]
+    fn test_spawn_over_stack() {
+        let config = Config { board: Board { buffer: 0, ..Board::default() }, ..Config::default() };
+        let mut piece_queue = PieceQueue::new(RandomizerKind::SevenBag, 5);
+        while piece_queue.peek_piece() != SHAPE_I {
+            piece_queue.next_piece();
+        }
+
+        // an I piece in the first lane covers a fourth column, past the 3-wide pieces,
+        // so it spawns in the next lane instead of on top of the block
+        let mut fallen_blocks = FallenBlocksType::new();
+        fallen_blocks.insert(Pivot { x: 4, y: 1 }, block(1));
+        assert!(!config.board.is_game_over(&fallen_blocks, SHAPE_I));
+
+        let mut active_players = ActivePlayersType::new();
+        let mut inactive_players : VecDeque<PieceState> = vec![PieceState::new(1)].into();
+        activate_piece(&mut active_players, &mut inactive_players, &fallen_blocks, &config, &mut piece_queue, &400.0, 0);
+        assert_eq!(active_players[&1].shape, SHAPE_I);
+        assert_eq!(active_players[&1].pivot, Pivot { x: 6, y: 0 });
+        assert!(!fallen_blocks_collision(&active_players[&1], &fallen_blocks, &config.board));
+
+        // once its fourth column is blocked in every lane, the game is over
+        for x in [9, 14, 19] {
+            fallen_blocks.insert(Pivot { x, y: 1 }, block(1));
+        }
+        assert!(config.board.is_game_over(&fallen_blocks, SHAPE_I));
+        assert!(!config.board.is_game_over(&fallen_blocks, SHAPE_T));
+    }
+
+    #[test]
+    fn test_line_clear_credit() {
+        // the bottom row is shared between players 1 and 2, the one above belongs to player 3
+        let mut fallen_blocks = FallenBlocksType::new();
+        for x in 0..Board::default().width {
+            let player_id = if x < 10 { 1 } else { 2 };
+            fallen_blocks.insert(Pivot { x, y: 19 }, FallenBlock { shape: 0, player_id });
+        }
+        fallen_blocks.insert(Pivot { x: 4, y: 18 }, FallenBlock { shape: 2, player_id: 3 });
+
+        assert_eq!(clear_lines(&mut fallen_blocks, &Board::default()), vec![vec![1, 2]]);
+        assert_eq!(fallen_blocks.len(), 1);
+        assert_eq!(fallen_blocks[&Pivot { x: 4, y: 19 }], FallenBlock { shape: 2, player_id: 3 });
+
+        let mut stats = GameStats::default();
+        stats.record_lock(1, true, 800, &[vec![1, 2], vec![1], vec![1], vec![3]], 0);
+        stats.record_lock(2, false, 0, &[], 0);
+        for _ in 0..30 {
+            stats.record_action(2, 0);
+        }
+
+        let summary = stats.summary(60_000);
+        assert_eq!(summary.iter().map(|player| player.player_id).collect::<Vec<usize>>(), vec![1, 2, 3]);
+        assert_eq!((summary[0].pieces_placed, summary[0].lines_cleared, summary[0].tetrises), (1, 3, 1));
+        assert_eq!((summary[0].hard_drops, summary[0].points), (1, 800));
+        assert_eq!((summary[1].pieces_placed, summary[1].lines_cleared, summary[1].tetrises), (1, 1, 0));
+        assert_eq!(summary[1].apm, 30.0);
+        assert_eq!(summary[2].lines_cleared, 1);
+
+        // the APM changing on its own doesn't need to be sent
+        assert!(summary[1].same_counts(&stats.summary(120_000)[1]));
+    }
+
+    #[test]
+    fn test_config() {
+        let config = Config::from_toml(r#"
+            frame_millis = 10
+            randomizer = "tgm"
+
+            [board]
+            width = 12
+
+            [scoring]
+            combo = 0
+        "#).unwrap();
+        assert_eq!(config.frame_millis, 10);
+        assert_eq!(config.randomizer, RandomizerKind::TgmHistory);
+        assert_eq!(config.board, Board { width: 12, ..Board::default() });
+        assert_eq!(config.scoring.combo, 0);
+        assert_eq!(config.scoring.lines, ScoringTable::default().lines);
+        assert_eq!(config.max_lock_resets, Config::default().max_lock_resets);
+        assert!(config.validate().is_ok());
+
+        // typos are errors rather than being silently ignored
+        assert!(Config::from_toml("frame_milis = 10").is_err());
+        assert!(Config::from_toml("randomizer = \"8bag\"").is_err());
+
+        let args = ["--frame-millis", "20", "--seed=5", "--width", "30", "--max-active", "3"];
+        let config = Config::load(args.iter().map(|arg| arg.to_string())).unwrap();
+        assert_eq!((config.frame_millis, config.seed, config.max_active), (20, Some(5), Some(3)));
+        assert_eq!(config.board.width, 30);
+
+        let load = |args: &[&str]| Config::load(args.iter().map(|arg| arg.to_string()));
+        assert!(load(&["--frame-millis"]).is_err());
+        assert!(load(&["--colour", "red"]).is_err());
+        // the server's own settings aren't part of the game's
+        assert!(load(&["--ping-millis", "500"]).is_err());
+        assert!(load(&["--seed", "-1"]).is_err());
+        assert!(load(&["frame-millis", "20"]).is_err());
+
+        // settings which parse but don't make sense are caught too
+        assert!(load(&["--width", "4"]).is_err());
+        assert!(load(&["--min-shift-period", "500"]).is_err());
+        assert!(load(&["--max-active", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let mut prev = GameSnapshot { seed: 1, ..GameSnapshot::default() };
+        prev.fallen_blocks.insert(Pivot { x: 0, y: 19 }, block(1));
+        prev.fallen_blocks.insert(Pivot { x: 1, y: 19 }, block(1));
+        prev.piece_states.insert(1, test_piece(1, 5, 0));
+        prev.piece_states.insert(2, test_piece(2, 12, 0));
+        prev.player_queue = vec![3];
+        prev.piece_queue = vec![0, 1, 2];
+
+        // nothing changed, so there is nothing to send
+        assert!(prev.diff(&prev).is_empty());
+
+        let mut next = prev.clone();
+        next.fallen_blocks.remove(&Pivot { x: 0, y: 19 });
+        next.fallen_blocks.insert(Pivot { x: 2, y: 19 }, block(4));
+        next.piece_states.get_mut(&1).unwrap().pivot.y = 1;
+        next.piece_states.remove(&2);
+        next.score = 100;
+
+        let delta = next.diff(&prev);
+        assert_eq!(delta.removed_blocks, vec![Pivot { x: 0, y: 19 }]);
+        assert_eq!(delta.added_blocks.len(), 1);
+        assert_eq!(delta.added_blocks[0].position, Pivot { x: 2, y: 19 });
+        assert_eq!(delta.moved_pieces, vec![test_piece(1, 5, 1)]);
+        assert_eq!(delta.removed_pieces, vec![2]);
+        assert_eq!(delta.player_queue, None);
+        assert_eq!(delta.piece_queue, None);
+        assert_eq!(delta.score, Some(100));
+
+        let message = delta.to_message(7);
+        assert!(message["type"] == "gameDelta");
+        assert!(message["seq"] == 7);
+        assert!(message.get("player_queue").is_none());
+        assert!(message.get("stats").is_none());
+    }
+
+    #[test]
+    fn test_tick() {
+        let config = Config::default();
+        let shift_ticks = config.max_shift_period as u64 / config.frame_millis + 1;
+        let mut game = Game::new(config, 5);
+        game.add_player(1);
+
+        // nothing spawns until a whole shift period has passed
+        for _ in 0..shift_ticks - 1 {
+            game.tick(vec![]);
+        }
+        assert!(game.state().active_players.is_empty());
+
+        game.tick(vec![]);
+        let spawned = game.state().active_players[&1].pivot;
+
+        // the piece falls one row once another shift period has passed
+        for _ in 0..shift_ticks - 1 {
+            game.tick(vec![]);
+        }
+        assert_eq!(game.state().active_players[&1].pivot, spawned);
+
+        game.tick(vec![]);
+        assert_eq!(game.state().active_players[&1].pivot, Pivot { y: spawned.y + 1, ..spawned });
+
+        // inputs are applied at the start of the tick they are given to
+        let left = KeyState { left: true, player_id: 1, ..KeyState::default() };
+        game.tick(vec![Input::Keys(left)]);
+        assert_eq!(game.state().active_players[&1].pivot, Pivot { x: spawned.x - 1, y: spawned.y + 1 });
+        assert_eq!(game.state().score.stats.summary(game.state().now())[0].actions, 1);
+
+        // a new game starts with everyone still in it, waiting for a piece
+        let seed = game.seed();
+        game.add_player(2);
+        game.reset();
+        assert_ne!(game.seed(), seed);
+        assert!(game.state().active_players.is_empty());
+        assert_eq!(game.player_ids(), vec![1, 2]);
+        assert!(game.is_playing(2));
+    }
+
+    #[test]
+    fn test_input_queue() {
+        let config = Config::default();
+        let shift_ticks = config.max_shift_period as u64 / config.frame_millis + 1;
+        let mut game = Game::new(config, 5);
+        game.add_player(1);
+        for _ in 0..shift_ticks {
+            game.tick(vec![]);
+        }
+        let spawned = game.state().active_players[&1].pivot;
+        let x = |game : &Game| game.state().active_players[&1].pivot.x;
+
+        let input = |frame : u64, left : bool| Input::Keys(KeyState {
+            left,
+            right: !left,
+            player_id: 1,
+            frame: Some(frame),
+            ..KeyState::default()
+        });
+
+        // out of order, with a duplicate and an input which presses nothing
+        let idle = Input::Keys(KeyState { player_id: 1, frame: Some(4), ..KeyState::default() });
+        game.tick(vec![input(3, true), input(1, true), input(2, false), input(2, false), idle]);
+        assert_eq!(x(&game), spawned.x - 1);
+        assert_eq!(game.state().input_frames[&1], 1);
+
+        game.tick(vec![]);
+        assert_eq!(x(&game), spawned.x);
+        assert_eq!(game.state().input_frames[&1], 2);
+
+        // inputs older than one already applied are dropped
+        game.tick(vec![input(1, false)]);
+        assert_eq!(x(&game), spawned.x - 1);
+        assert_eq!(game.state().input_frames[&1], 3);
+
+        game.tick(vec![]);
+        assert_eq!(x(&game), spawned.x - 1);
+
+        // with a higher limit, more of the queue is applied at once
+        let config = Config { max_inputs_per_tick: 2, ..Config::default() };
+        let mut game = Game::new(config, 5);
+        game.add_player(1);
+        for _ in 0..shift_ticks {
+            game.tick(vec![]);
+        }
+        game.tick(vec![input(1, false), input(2, false), input(3, false)]);
+        assert_eq!(x(&game), spawned.x + 2);
+        assert_eq!(game.state().input_frames[&1], 2);
+    }
+
+    #[test]
+    fn test_input_queue_overflow() {
+        let config = Config { max_queued_inputs: 4, ..Config::default() };
+        let shift_ticks = config.max_shift_period as u64 / config.frame_millis + 1;
+        let das_ticks = config.das_millis / config.frame_millis + 1;
+        let mut game = Game::new(config, 5);
+        game.add_player(1);
+        for _ in 0..shift_ticks {
+            game.tick(vec![]);
+        }
+        let spawned = game.state().active_players[&1].pivot;
+        let x = |game : &Game| game.state().active_players[&1].pivot.x;
+        let key = |key : Key, pressed : bool| Input::Key(KeyEvent { key, pressed, player_id: 1, frame: None });
+
+        game.tick(vec![key(Key::Right, true)]);
+        assert_eq!(x(&game), spawned.x + 1);
+
+        // the queue fills up with rotations, but letting go of right still gets in
+        let mut inputs : Vec<Input> = (0..10).map(|_| key(Key::Rot, true)).collect();
+        inputs.push(key(Key::Right, false));
+        game.tick(inputs);
+        for _ in 0..das_ticks * 2 {
+            game.tick(vec![]);
+        }
+        assert_eq!(x(&game), spawned.x + 1);
+    }
+
+    #[test]
+    fn test_auto_repeat() {
+        let config = Config::default();
+        let shift_ticks = config.max_shift_period as u64 / config.frame_millis + 1;
+        let mut game = Game::new(config.clone(), 5);
+        game.add_player(1);
+        for _ in 0..shift_ticks {
+            game.tick(vec![]);
+        }
+        let spawned = game.state().active_players[&1].pivot;
+        let x = |game : &Game| game.state().active_players[&1].pivot.x;
+        let key = |key : Key, pressed : bool| Input::Key(KeyEvent { key, pressed, player_id: 1, frame: None });
+
+        // pressing a key shifts once straight away
+        game.tick(vec![key(Key::Right, true)]);
+        assert_eq!(x(&game), spawned.x + 1);
+
+        // then again once the DAS has passed, then every ARR
+        let das_ticks = config.das_millis / config.frame_millis + 1;
+        for _ in 0..das_ticks - 1 {
+            game.tick(vec![]);
+        }
+        assert_eq!(x(&game), spawned.x + 1);
+        game.tick(vec![]);
+        assert_eq!(x(&game), spawned.x + 2);
+
+        let arr_ticks = config.arr_millis / config.frame_millis + 1;
+        for _ in 0..arr_ticks {
+            game.tick(vec![]);
+        }
+        assert_eq!(x(&game), spawned.x + 3);
+
+        // letting go stops the repeat
+        game.tick(vec![key(Key::Right, false)]);
+        for _ in 0..das_ticks {
+            game.tick(vec![]);
+        }
+        assert_eq!(x(&game), spawned.x + 3);
+
+        // with no delay and no repeat rate the piece goes straight to the wall
+        game.set_handling(1, &HandlingUpdate { das_millis: Some(0), arr_millis: Some(0), ..HandlingUpdate::default() });
+        game.tick(vec![key(Key::Left, true)]);
+        let wall = x(&game);
+        assert!(wall < spawned.x);
+        game.tick(vec![key(Key::Left, false)]);
+        game.tick(vec![key(Key::Left, true)]);
+        assert_eq!(x(&game), wall);
+
+        // holding fast drop keeps the piece dropping at the player's own rate until it's let go
+        game.set_handling(1, &HandlingUpdate { soft_drop_millis: Some(50), ..HandlingUpdate::default() });
+        game.tick(vec![key(Key::FastDrop, true)]);
+        let piece = game.state().active_players[&1];
+        assert!(piece.fast_drop);
+        assert_eq!(piece.next_shift_time, Some(game.state().now() + 50));
+
+        game.tick(vec![key(Key::FastDrop, false)]);
+        assert!(!game.state().active_players[&1].fast_drop);
+    }
+
+    #[test]
+    fn test_handling() {
+        let handling = Handling::new(&Config::default());
+        assert_eq!(handling.soft_drop_millis, Config::default().fast_drop_shift_millis);
+
+        // settings out of range are clamped, and settings left out are kept
+        let update = HandlingUpdate { das_millis: Some(5000), soft_drop_millis: Some(0), ..HandlingUpdate::default() };
+        let updated = handling.updated(&update);
+        assert_eq!(updated.das_millis, 1000);
+        assert_eq!(updated.arr_millis, handling.arr_millis);
+        assert_eq!(updated.soft_drop_millis, 1);
+
+        // key events and handling updates come over the wire as tagged messages
+        let event : KeyEvent = serde_json::from_str(r#"{"type": "key", "key": "fast_drop", "pressed": true, "frame": 3}"#).unwrap();
+        assert_eq!(event, KeyEvent { key: Key::FastDrop, pressed: true, player_id: 0, frame: Some(3) });
+        assert!(serde_json::from_str::<KeyEvent>(r#"{"left": true}"#).is_err());
+    }
+
+    #[test]
+    fn test_ticker() {
+        let clock = ManualClock::default();
+        let mut ticker = Ticker::new(16, clock.now());
+
+        clock.advance(15);
+        assert_eq!(ticker.due(clock.now()), 0);
+        assert_eq!(ticker.until_next(clock.now()), 1);
+
+        clock.advance(1);
+        assert_eq!(ticker.due(clock.now()), 1);
+        assert_eq!(ticker.due(clock.now()), 0);
+
+        // a thread which overslept runs the ticks it missed, and stays on schedule
+        clock.advance(50);
+        assert_eq!(ticker.due(clock.now()), 3);
+        assert_eq!(ticker.until_next(clock.now()), 14);
+
+        // but it gives up on catching up after falling too far behind
+        clock.advance(10_000);
+        assert_eq!(ticker.due(clock.now()), MAX_CATCH_UP_TICKS);
+        assert_eq!(ticker.due(clock.now()), 0);
+        assert_eq!(ticker.until_next(clock.now()), 16);
+    }
+
+    // a T at x resting on the floor of an empty board, with the given rotation
+    fn grounded_piece(x: i8, rotation: u8, fallen_blocks: &FallenBlocksType, config: &Config) -> PieceState {
+        let mut piece = test_piece(1, x, 0);
+        piece.rotation = rotation;
+        while !is_grounded(&piece, fallen_blocks, &config.board) {
+            piece.pivot.y += 1;
+        }
+        piece.lowest_row = piece.pivot.y;
+        return piece;
+    }
+
+    #[test]
+    fn test_lock_delay() {
+        let config = Config::default();
+        let bottom_touch = config.bottom_touch_millis as u128;
+        let fallen_blocks = FallenBlocksType::new();
+        let mut active_players = ActivePlayersType::new();
+        active_players.insert(1, grounded_piece(5, 0, &fallen_blocks, &config));
+
+        let shift = |active_players: &mut ActivePlayersType, right: bool, now: u128| {
+            let input = KeyState {
+                left: !right,
+                right,
+                player_id: 1,
+                player_name: "guest".to_string(),
+                ..KeyState::default()
+            };
+            update_state(active_players, &input, &fallen_blocks, &config, now);
+        };
+
+        // every move along the floor starts the lock delay again
+        for i in 0..config.max_lock_resets {
+            let now = 100 * (i as u128 + 1);
+            shift(&mut active_players, i % 2 == 0, now);
+            assert_eq!(active_players[&1].next_shift_time, Some(now + bottom_touch));
+            assert_eq!(active_players[&1].lock_resets, i + 1);
+        }
+
+        // until the resets run out, then the piece still moves but the delay keeps running down
+        let before = active_players[&1];
+        shift(&mut active_players, true, 10_000);
+        assert_eq!(active_players[&1].pivot.x, before.pivot.x + 1);
+        assert_eq!(active_players[&1].next_shift_time, before.next_shift_time);
+
+        // landing again on the same row then locks on the next tick
+        let mut piece = active_players[&1];
+        track_lowest_row(&mut piece);
+        start_lock_delay(&mut piece, &config, 20_000);
+        assert_eq!(piece.next_shift_time, Some(20_000));
+
+        // but reaching a lower row gives the piece all of its resets back
+        piece.pivot.y -= 1;
+        piece.lowest_row = piece.pivot.y;
+        piece.pivot.y += 1;
+        track_lowest_row(&mut piece);
+        assert_eq!(piece.lock_resets, 0);
+        start_lock_delay(&mut piece, &config, 20_000);
+        assert_eq!(piece.next_shift_time, Some(20_000 + bottom_touch));
+
+        // rotating on the floor resets the delay too
+        let mut active_players = ActivePlayersType::new();
+        active_players.insert(1, grounded_piece(5, 1, &fallen_blocks, &config));
+        rotate(&mut active_players, &fallen_blocks, true);
+        assert_eq!(active_players[&1].rotation, 2);
+        assert_eq!(active_players[&1].next_shift_time, Some(bottom_touch));
+        assert_eq!(active_players[&1].lock_resets, 1);
+
+        // moving in the air doesn't use up a reset
+        let mut piece = test_piece(1, 5, 2);
+        piece.lowest_row = 2;
+        let mut active_players = ActivePlayersType::new();
+        active_players.insert(1, piece);
+        shift(&mut active_players, true, 100);
+        assert_eq!(active_players[&1].lock_resets, 0);
+        assert_eq!(active_players[&1].next_shift_time, None);
+    }
+
+    #[test]
+    fn test_ghost_piece() {
+        let board = Board::default();
+        let mut fallen_blocks = FallenBlocksType::new();
+        let mut active_players = ActivePlayersType::new();
+        let piece = test_piece(1, 5, 0);
+        active_players.insert(1, piece);
+
+        // on an empty board the piece lands on the floor
+        assert_eq!(landing_position(&piece, &active_players, &fallen_blocks, &board, CollisionPolicy::Solid), Pivot { x: 5, y: 18 });
+
+        // or on top of the stack
+        fallen_blocks.insert(Pivot { x: 6, y: 15 }, block(0));
+        assert_eq!(landing_position(&piece, &active_players, &fallen_blocks, &board, CollisionPolicy::Solid), Pivot { x: 5, y: 13 });
+
+        // or on top of another player's piece in the way
+        let mut other = test_piece(2, 5, 8);
+        other.shape = SHAPE_O;
+        active_players.insert(2, other);
+        assert_eq!(landing_position(&piece, &active_players, &fallen_blocks, &board, CollisionPolicy::Solid), Pivot { x: 5, y: 7 });
+
+        // a piece which can't fall any further lands where it is
+        let resting = test_piece(3, 5, 18);
+        assert_eq!(landing_position(&resting, &ActivePlayersType::new(), &FallenBlocksType::new(), &board, CollisionPolicy::Solid), resting.pivot);
+
+        // every piece in a snapshot carries its landing position
+        let mut state = GameState::new(Config::default(), 1);
+        state.active_players = active_players;
+        state.fallen_blocks = fallen_blocks;
+        let snapshot = GameSnapshot::new(&state, vec![]);
+        assert_eq!(snapshot.piece_states[&1].ghost, Some(Pivot { x: 5, y: 7 }));
+        assert_eq!(snapshot.piece_states[&2].ghost, Some(Pivot { x: 5, y: 12 }));
+        assert_eq!(state.active_players[&1].ghost, None);
+    }
+
+    #[test]
+    fn test_hard_drop() {
+        let config = Config::default();
+        let shift_ticks = config.max_shift_period as u64 / config.frame_millis + 1;
+        let mut game = Game::new(config, 5);
+        game.add_player(1);
+        for _ in 0..shift_ticks {
+            game.tick(vec![]);
+        }
+        let piece = game.state().active_players[&1];
+        let landing = landing_position(&piece, &game.state().active_players, &game.state().fallen_blocks, &game.state().config.board, CollisionPolicy::Solid);
+        let rows = (landing.y - piece.pivot.y) as u32;
+        assert!(rows > 0);
+
+        // the piece lands and locks in the same tick, with 2 points for every row it fell
+        let hard_drop = KeyState { hard_drop: true, player_id: 1, ..KeyState::default() };
+        game.tick(vec![Input::Keys(hard_drop)]);
+        let mut landed = piece;
+        landed.pivot = landing;
+        let mut cells = piece_cells(&landed);
+        cells.sort_by_key(|cell| (cell.x, cell.y));
+        let mut fallen : Vec<Pivot> = game.state().fallen_blocks.keys().copied().collect();
+        fallen.sort_by_key(|cell| (cell.x, cell.y));
+        assert_eq!(fallen, cells);
+        assert_eq!(game.state().score.points, 2 * rows);
+
+        // drop points don't count towards the speed, only lines cleared
+        assert_eq!(game.state().score.lines, 0);
+
+        let stats = game.state().score.stats.summary(game.state().now());
+        assert_eq!((stats[0].hard_drops, stats[0].pieces_placed, stats[0].points), (1, 1, 2 * rows));
+    }
+
+    #[test]
+    fn test_hard_drop_onto_piece() {
+        // a T above another player's O, in the same columns
+        let pieces = || {
+            let mut active_players = ActivePlayersType::new();
+            let mut top = test_piece(1, 5, 0);
+            top.next_shift_time = Some(u128::MAX);
+            let mut bottom = test_piece(2, 5, 8);
+            bottom.shape = SHAPE_O;
+            bottom.next_shift_time = Some(u128::MAX);
+            active_players.insert(1, top);
+            active_players.insert(2, bottom);
+            return active_players;
+        };
+        let hard_drop = |player_id| Input::Keys(KeyState { hard_drop: true, player_id, ..KeyState::default() });
+        let floor = Board::default().height - 1;
+
+        // the T stops on top of the O and locks there, and the O carries on
+        let mut state = GameState::new(Config::default(), 1);
+        state.active_players = pieces();
+        tick(&mut state, vec![hard_drop(1)]);
+        assert!(!state.active_players.contains_key(&1));
+        assert_eq!(state.active_players[&2].pivot, Pivot { x: 5, y: 8 });
+        assert!(state.fallen_blocks.contains_key(&Pivot { x: 6, y: 8 }));
+        assert_eq!(state.score.points, 2 * 7);
+
+        // if both are hard dropped at once, the O locks first and the T lands on it
+        let mut state = GameState::new(Config::default(), 1);
+        state.active_players = pieces();
+        tick(&mut state, vec![hard_drop(1), hard_drop(2)]);
+        assert!(state.active_players.is_empty());
+        assert!(state.fallen_blocks.contains_key(&Pivot { x: 6, y: floor }));
+        assert!(state.fallen_blocks.contains_key(&Pivot { x: 6, y: floor - 2 }));
+        assert_eq!(state.fallen_blocks.len(), 8);
+    }
+
+    #[test]
+    fn test_collision_policy() {
+        // a T falling onto another player's O, which isn't falling itself
+        let state = |collision : CollisionPolicy, o_y : i8| {
+            let config = Config { collision, ..Config::default() };
+            let mut state = GameState::new(config, 1);
+            let mut top = test_piece(1, 5, 10);
+            top.next_shift_time = Some(0);
+            let mut bottom = test_piece(2, 5, o_y);
+            bottom.shape = SHAPE_O;
+            bottom.next_shift_time = Some(u128::MAX);
+            state.active_players.insert(1, top);
+            state.active_players.insert(2, bottom);
+            return state;
+        };
+        let pivot = |state : &GameState, player_id| state.active_players[&player_id].pivot;
+
+        // solid pieces wait for each other
+        let mut solid = state(CollisionPolicy::Solid, 11);
+        tick(&mut solid, vec![]);
+        assert_eq!((pivot(&solid, 1).y, pivot(&solid, 2).y), (10, 11));
+
+        // pieces which pass through each other don't
+        let mut pass = state(CollisionPolicy::PassThrough, 11);
+        tick(&mut pass, vec![]);
+        assert_eq!((pivot(&pass, 1).y, pivot(&pass, 2).y), (11, 11));
+        assert_eq!(landing_position(&pass.active_players[&1], &pass.active_players, &pass.fallen_blocks,
+                                    &pass.config.board, CollisionPolicy::PassThrough).y, 18);
+
+        // a falling piece pushes the one below it down
+        let mut push = state(CollisionPolicy::Push, 11);
+        tick(&mut push, vec![]);
+        assert_eq!((pivot(&push, 1).y, pivot(&push, 2).y), (11, 12));
+
+        // the ghost is where a hard drop locks, on top of the piece below
+        // without pushing it, though falling would have pushed it
+        let mut push = state(CollisionPolicy::Push, 14);
+        let ghost = GameSnapshot::new(&push, vec![]).piece_states[&1].ghost;
+        assert_eq!(ghost, Some(Pivot { x: 5, y: 13 }));
+        tick(&mut push, vec![Input::Keys(KeyState { hard_drop: true, player_id: 1, ..KeyState::default() })]);
+        assert!(push.fallen_blocks.contains_key(&Pivot { x: 6, y: 13 }));
+        assert_eq!(pivot(&push, 2).y, 14);
+
+        // unless that one is resting on the floor
+        let mut push = state(CollisionPolicy::Push, 17);
+        push.active_players.get_mut(&1).unwrap().pivot.y = 16;
+        tick(&mut push, vec![]);
+        assert_eq!((pivot(&push, 1).y, pivot(&push, 2).y), (16, 17));
+
+        // a piece waiting for one which isn't waiting for it keeps waiting
+        let deadlock_ticks = solid.config.deadlock_millis.div_ceil(solid.config.frame_millis);
+        for _ in 0..deadlock_ticks * 2 {
+            tick(&mut solid, vec![]);
+        }
+        assert_eq!((pivot(&solid, 1).y, pivot(&solid, 2).y), (10, 11));
+        assert_eq!(solid.active_players[&1].blocked_since, None);
+
+        // but of two pieces which hold each other up, one locks after deadlock_millis
+        let mut stuck = state(CollisionPolicy::Solid, 10);
+        for player_id in [1, 2] {
+            let piece = stuck.active_players.get_mut(&player_id).unwrap();
+            piece.shape = SHAPE_O;
+            piece.next_shift_time = Some(0);
+        }
+        for _ in 0..deadlock_ticks {
+            tick(&mut stuck, vec![]);
+        }
+        assert_eq!(stuck.active_players.len(), 2);
+        tick(&mut stuck, vec![]);
+        assert!(!stuck.active_players.contains_key(&1));
+        assert!(stuck.fallen_blocks.contains_key(&Pivot { x: 6, y: 11 }));
+        assert_eq!(pivot(&stuck, 2).y, 8);
+    }
+
+    #[test]
+    fn test_pass_through_lock() {
+        // two overlapping O pieces, the lower one resting on the floor
+        let config = Config { collision: CollisionPolicy::PassThrough, ..Config::default() };
+        let mut state = GameState::new(config, 1);
+        for (player_id, y) in [(1, 17), (2, 16)] {
+            let mut piece = test_piece(player_id, 5, y);
+            piece.shape = SHAPE_O;
+            piece.next_shift_time = Some(0);
+            state.active_players.insert(player_id, piece);
+        }
+
+        // the lower one locks, and the other is lifted out of it
+        tick(&mut state, vec![]);
+        assert!(!state.active_players.contains_key(&1));
+        assert_eq!(state.active_players[&2].pivot, Pivot { x: 5, y: 15 });
+
+        // so both keep all of their blocks when it locks too
+        state.active_players.get_mut(&2).unwrap().next_shift_time = Some(0);
+        tick(&mut state, vec![]);
+        assert!(state.active_players.is_empty());
+        assert_eq!(state.fallen_blocks.len(), 8);
+        for player_id in [1, 2] {
+            assert_eq!(state.fallen_blocks.values().filter(|block| block.player_id == player_id).count(), 4);
+        }
+    }
+
+    #[test]
+    fn test_pass_through_hard_drop_lifted_off() {
+        // two overlapping O pieces hard dropped together onto a stack
+        // which reaches the top of the board
+        let config = Config { collision: CollisionPolicy::PassThrough, ..Config::default() };
+        let mut state = GameState::new(config, 1);
+        for x in 4..8 {
+            for y in 0..state.config.board.height {
+                state.fallen_blocks.insert(Pivot { x, y }, block(0));
+            }
+        }
+        for player_id in [1, 2] {
+            // filling the buffer zone, on top of the stack
+            let mut piece = test_piece(player_id, 5, -3);
+            piece.shape = SHAPE_O;
+            piece.hard_drop = true;
+            piece.next_shift_time = Some(u128::MAX);
+            state.active_players.insert(player_id, piece);
+        }
+
+        // the first locks where it is, and the second has no room to be
+        // lifted into so it goes back to wait instead of dropping too,
+        // leaving only the first player's piece placed when the game ends
+        let events = tick(&mut state, vec![]);
+        let game_over = events.game_over.unwrap();
+        let placed : Vec<(usize, u32)> = game_over.stats.iter()
+            .map(|stats| (stats.player_id, stats.pieces_placed))
+            .filter(|(_, pieces_placed)| *pieces_placed > 0)
+            .collect();
+        assert_eq!(placed, vec![(1, 1)]);
+    }
+}
@@ -0,0 +1,674 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::piece_state::{PieceState, Pivot, ActivePlayersType, FallenBlocksType};
+use crate::input::{KeyState};
+use crate::scoring::TSpin;
+use crate::board::Board;
+use crate::config::Config;
+
+// TODO: Cleaner representation of pieces for calculations
+// consider classes
+const PIECE_Z : [bool ; 9] = [ true, true, false, false, true, true, false, false, false]; //0
+const PIECE_S : [bool ; 9] = [ false, true, true, true, true, false, false, false, false]; //1
+const PIECE_J : [bool ; 9] = [ true, false, false, true, true, true, false, false, false]; //2
+const PIECE_L : [bool ; 9] = [ false, false, true, true, true, true, false, false, false]; //3
+const PIECE_T : [bool ; 9] = [ false, true, false, true, true, true, false, false, false]; //4
+const PIECE_I : [bool ; 16] = [ false, false, false, false, true, true, true, true, false, false, false, false, false, false, false, false]; //5
+const PIECE_O : [bool ; 16] = [ false, false, false, false, false, true, true, false, false, true, true, false, false, false, false, false]; //6
+
+pub const SHAPE_T : u8 = 4;
+pub const SHAPE_I : u8 = 5;
+pub const SHAPE_O : u8 = 6;
+
+// Number of positions tried for each rotation, the first is no kick at all
+pub const NUM_KICKS : usize = 5;
+
+/*
+SRS wallkick offsets as (x, y), indexed by [rotation before][clockwise ? 0 : 1].
+Rotations are 0 = spawn, 1 = R (clockwise), 2 = upside down, 3 = L.
+
+These are copied from the guideline tables, so y points UP, unlike the board
+where y points down. Use kick_offset() to read them.
+*/
+const JLSTZ_KICKS : [ [ [(i8, i8) ; NUM_KICKS] ; 2] ; 4] = [
+    // 0->R, 0->L
+    [[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+     [(0, 0), ( 1, 0), ( 1, 1), (0, -2), ( 1, -2)]],
+    // R->2, R->0
+    [[(0, 0), ( 1, 0), ( 1, -1), (0, 2), ( 1, 2)],
+     [(0, 0), ( 1, 0), ( 1, -1), (0, 2), ( 1, 2)]],
+    // 2->L, 2->R
+    [[(0, 0), ( 1, 0), ( 1, 1), (0, -2), ( 1, -2)],
+     [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)]],
+    // L->0, L->2
+    [[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+     [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)]],
+];
+
+const I_KICKS : [ [ [(i8, i8) ; NUM_KICKS] ; 2] ; 4] = [
+    // 0->R, 0->L
+    [[(0, 0), (-2, 0), ( 1, 0), (-2, -1), ( 1, 2)],
+     [(0, 0), (-1, 0), ( 2, 0), (-1, 2), ( 2, -1)]],
+    // R->2, R->0
+    [[(0, 0), (-1, 0), ( 2, 0), (-1, 2), ( 2, -1)],
+     [(0, 0), ( 2, 0), (-1, 0), ( 2, 1), (-1, -2)]],
+    // 2->L, 2->R
+    [[(0, 0), ( 2, 0), (-1, 0), ( 2, 1), (-1, -2)],
+     [(0, 0), ( 1, 0), (-2, 0), ( 1, -2), (-2, 1)]],
+    // L->0, L->2
+    [[(0, 0), ( 1, 0), (-2, 0), ( 1, -2), (-2, 1)],
+     [(0, 0), (-2, 0), ( 1, 0), (-2, -1), ( 1, 2)]],
+];
+
+const ROT_LIMIT : u8 = 4;
+
+// returns false if the player isn't active, so the input was ignored
+pub fn update_state(active_players : &mut ActivePlayersType,
+                    player_input : &KeyState,
+                    fallen_blocks : &FallenBlocksType,
+                    config : &Config,
+                    now : u128) -> bool {
+
+    let player_id = player_input.player_id;
+    let active_player_ids : Vec<usize> = active_players.keys().copied().collect();
+
+    // only apply the update if the player specified in player_id is active
+    if !active_player_ids.contains(&player_id) {
+        return false;
+    }
+
+    let old_state = active_players[&player_id];
+    let mut new_state = apply_input(player_input, active_players, fallen_blocks, config, now);
+    if !collision(&new_state, active_players, fallen_blocks, &config.board, config.collision) {
+        let moved = new_state.pivot != old_state.pivot || new_state.rotation != old_state.rotation;
+        if moved && !new_state.hard_drop {
+            reset_lock_delay(&mut new_state, fallen_blocks, config, now);
+        }
+
+        // call unwrap() to make sure that there was already a key present and
+        // that we are just updating an existing entry
+        active_players.insert(player_id, new_state).unwrap();
+    }
+    return true;
+}
+
+/**
+ *
+ *  Whether a piece is resting on the floor or the stack, so that it
+ *  would lock rather than fall if it were shifted down now.
+ *
+ */
+pub fn is_grounded(piece : &PieceState,
+                   fallen_blocks : &FallenBlocksType,
+                   board : &Board) -> bool {
+    let mut below = *piece;
+    below.pivot.y += 1;
+    return fallen_blocks_collision(&below, fallen_blocks, board);
+}
+
+/**
+ *
+ *  The lowest-row rule: a piece which reaches a row further down than
+ *  it has been before gets all of its lock delay resets back, so that
+ *  resets used higher up the stack don't count against it.
+ *
+ */
+pub fn track_lowest_row(piece : &mut PieceState) {
+    if piece.pivot.y > piece.lowest_row {
+        piece.lowest_row = piece.pivot.y;
+        piece.lock_resets = 0;
+    }
+}
+
+/**
+ *
+ *  Starts the lock delay for a piece which has just landed. A piece
+ *  which has used up its resets and lands again on the same row locks
+ *  on the next tick instead, so it can't be kept in play forever by
+ *  stepping on and off a ledge.
+ *
+ */
+pub fn start_lock_delay(piece : &mut PieceState, config : &Config, now : u128) {
+    if piece.lock_resets >= config.max_lock_resets {
+        piece.next_shift_time = Some(now);
+    }
+    else {
+        piece.next_shift_time = Some(now + config.bottom_touch_millis as u128);
+    }
+}
+
+/**
+ *
+ *  Guideline lock delay, called after a piece successfully moves or
+ *  rotates. If the piece is resting on the stack, its lock delay
+ *  starts again, at most config.max_lock_resets times before it
+ *  reaches a lower row. After that the delay keeps running down.
+ *
+ */
+pub fn reset_lock_delay(piece : &mut PieceState,
+                        fallen_blocks : &FallenBlocksType,
+                        config : &Config,
+                        now : u128) {
+    // a kick may have moved the piece down
+    track_lowest_row(piece);
+
+    if !is_grounded(piece, fallen_blocks, &config.board) {
+        return;
+    }
+
+    if piece.lock_resets < config.max_lock_resets {
+        piece.lock_resets += 1;
+        piece.next_shift_time = Some(now + config.bottom_touch_millis as u128);
+    }
+}
+
+fn apply_input(player_input : &KeyState,
+               active_players : &mut ActivePlayersType,
+               fallen_blocks : &FallenBlocksType,
+               config : &Config,
+               now : u128) -> PieceState {
+
+
+    // make a copy of the current player state and work with this
+    let mut new_state = *active_players.get(&player_input.player_id).unwrap();
+    let mut name = [' '; 8];
+    let chars: Vec<char> = player_input.player_name.chars().collect();
+    for (i, x) in chars.iter().enumerate() {
+        if i < 8 {
+            name[i] = *x;
+        }
+    }
+    new_state.player_name = name;
+
+    if player_input.fast_drop {
+        new_state.fast_drop = true;
+        new_state.fast_drop_millis = config.fast_drop_shift_millis;
+        new_state.next_shift_time = Some(now + config.fast_drop_shift_millis as u128);
+    }
+
+    // the piece lands and locks later in the same tick, see game::hard_drop_piece
+    if player_input.hard_drop {
+        new_state.hard_drop = true;
+    }
+
+    // the hold itself needs the piece queue, so it happens later in the same
+    // tick, before the pieces fall, see game::hold_pieces
+    if player_input.hold && !new_state.hold_used {
+        new_state.hold_requested = true;
+    }
+
+    // Move left
+    if player_input.left {
+        new_state.pivot.x -= 1;
+    }
+    // Move right
+    if player_input.right {
+        new_state.pivot.x += 1;
+    }
+    // Moving means the last move is no longer a rotation
+    if player_input.left != player_input.right {
+        new_state.last_kick = None;
+    }
+    // Rotate clockwise
+    let mut rotated = false;
+    let mut clockwise = false;
+    if player_input.rot {
+        clockwise = true;
+        rotated = !rotated;
+        new_state.rotation = (new_state.rotation + 1) % ROT_LIMIT;
+    }
+    // Rotate counter-clockwise
+    if player_input.counter_rot {
+        rotated = !rotated;
+        new_state.rotation = (ROT_LIMIT + new_state.rotation - 1)
+                                % ROT_LIMIT;
+    }
+    // Only do wallkick calculations when there is a net rotation
+    if rotated {
+        return wallkick(&mut new_state, clockwise, active_players, fallen_blocks, &config.board, config.collision);
+    }
+    else {
+        return new_state;
+    }
+}
+/**
+ *
+ *  Determines whether there exists a block in the corresponding
+ *  rotated piece at the inputed (x, y) coordinate relative to the
+ *  top-left corner of the piece's bounding box.
+ *
+ *  This is done in place by taking advantage of how we can
+ *  represent both the original and rotated matrices as arrays, and
+ *  then represent the array of the rotated piece in terms of the
+ *  original.
+ *
+**/
+pub fn read_block(piece : &[bool], x : i8, y : i8, rot : u8) -> bool {
+    let length : i8 = piece.len().try_into().unwrap();
+    // Matrix width, 3 if there are 9 elements, 4 if there are 16
+    let width = if length == 9 {3} else {4};
+    // Return false if requested element is outside matrix
+    if x >= width || y >= width || x < 0 || y < 0 {
+        return false;
+    }
+    match rot {
+        // Unrotated
+        0 => {
+            let index = y * width + x;
+            return piece[index as usize];
+        }
+        // Rotated clockwise, goes down with x, left with y
+        1 => {
+            let index = (width - (x + 1) ) * width + y;
+            return piece[index as usize];
+        }
+        // 180 degrees, like unrotated but backwards
+        2 => {
+            let index = length - (y * width + x) - 1;
+            return piece[index as usize];
+        }
+        // Rotated counter-clockwise, goes up with x, right with y
+        3 => {
+            let index = x * width + (width - (y + 1) );
+            return piece[index as usize];
+        }
+        // Invalid Rotation
+        _ => false,
+    }
+}
+
+pub fn get_shape(shape_num : u8) -> &'static [bool] {
+    match shape_num {
+        0 => &PIECE_Z,
+        1 => &PIECE_S,
+        2 => &PIECE_J,
+        3 => &PIECE_L,
+        4 => &PIECE_T,
+        5 => &PIECE_I,
+        6 => &PIECE_O,
+        _unknown => panic!("Unknown Piece Number: {}", _unknown)
+    }
+}
+
+#[derive(PartialEq)]
+pub enum CollisionType {
+    Ceiling,
+    Wall,
+    Floor,
+    None,
+}
+
+/**
+ *
+ *  How the players' pieces treat each other, chosen per game.
+ *
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    // pieces can't overlap, a falling piece waits for the one below it
+    #[default]
+    Solid,
+    // pieces move through each other as if the others weren't there
+    PassThrough,
+    // like solid, but a falling piece pushes the piece below it down
+    Push,
+}
+
+impl CollisionPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CollisionPolicy::Solid => "solid",
+            CollisionPolicy::PassThrough => "passthrough",
+            CollisionPolicy::Push => "push",
+        }
+    }
+}
+
+impl fmt::Display for CollisionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for CollisionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CollisionPolicy, String> {
+        match s {
+            "solid" => Ok(CollisionPolicy::Solid),
+            "passthrough" => Ok(CollisionPolicy::PassThrough),
+            "push" => Ok(CollisionPolicy::Push),
+            _ => Err(format!("unknown collision policy {:?}, expected one of solid, passthrough, push", s)),
+        }
+    }
+}
+
+pub fn screen_collision(piece : &PieceState, board : &Board) -> CollisionType {
+    let this_shape = get_shape(piece.shape);
+    let width = if this_shape.len() == 9 {3} else {4};
+    let this_origin = piece.pivot;
+
+    for y in 0..width {
+        for x in 0..width {
+            let abs_x = x + this_origin.x;
+            let abs_y = y + this_origin.y;
+
+            if read_block(this_shape, x, y, piece.rotation) {
+                if !board.in_columns(abs_x) { return CollisionType::Wall };
+                if abs_y < board.top() { return CollisionType::Ceiling; }
+                if abs_y >= board.height { return CollisionType::Floor; }
+            }
+        }
+    }
+
+    return CollisionType::None;
+}
+
+pub fn fallen_blocks_collision(piece : &PieceState,
+                               fallen_blocks : &FallenBlocksType,
+                               board : &Board) -> bool {
+    // Check if we collide with the bottom of the screen
+    let bottom_screen_collision = screen_collision(piece, board) == CollisionType::Floor;
+    if bottom_screen_collision { return true; }
+
+    // check if we collide with any of the bottom blocks
+
+    let this_shape = get_shape(piece.shape);
+    let width = if this_shape.len() == 9 {3} else {4};
+    let this_origin = piece.pivot;
+
+    for y in 0..width {
+        for x in 0..width {
+            let abs_x = x + this_origin.x;
+            let abs_y = y + this_origin.y;
+
+            if read_block(this_shape, x, y, piece.rotation) {
+                // if the position of one of the blocks that makes up piece overlaps
+                // with the location of a block in fallen_blocks, we have a collision
+
+                if fallen_blocks.contains_key(&Pivot{x: abs_x, y: abs_y}) {
+                    return true;
+                }
+            }
+        }
+    }
+
+    return false;
+}
+
+// whether a piece overlaps any other player's piece, never under CollisionPolicy::PassThrough
+pub fn player_collision(piece : &PieceState,
+                        active_players : &ActivePlayersType,
+                        policy : CollisionPolicy) -> bool {
+    if policy == CollisionPolicy::PassThrough {
+        return false;
+    }
+
+    // Check if collides with other players
+    for (other_piece_id, other_piece) in active_players {
+        if piece.player_id != *other_piece_id && pieces_overlap(piece, other_piece) {
+            return true;
+        }
+    }
+    // TODO: add wallkicks
+    return false;
+}
+
+// whether any of the blocks of two pieces are in the same place
+pub fn pieces_overlap(piece : &PieceState, other_piece : &PieceState) -> bool {
+    let this_shape = get_shape(piece.shape);
+    let width = if this_shape.len() == 9 {3} else {4};
+    let this_origin = piece.pivot;
+
+    let other_origin = other_piece.pivot;
+    let other_shape = get_shape(other_piece.shape);
+    let x_offset = this_origin.x - other_origin.x;
+    let y_offset = this_origin.y - other_origin.y;
+    for x in 0..width {
+        for y in 0..width {
+            if read_block(this_shape, x, y, piece.rotation) &&
+                    read_block(other_shape, x + x_offset,
+                    y + y_offset, other_piece.rotation) {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
+// the lowest row any of a piece's blocks are in
+pub fn bottom_row(piece : &PieceState) -> i8 {
+    let this_shape = get_shape(piece.shape);
+    let width = if this_shape.len() == 9 {3} else {4};
+
+    let mut bottom = piece.pivot.y;
+    for y in 0..width {
+        for x in 0..width {
+            if read_block(this_shape, x, y, piece.rotation) {
+                bottom = bottom.max(piece.pivot.y + y);
+            }
+        }
+    }
+    return bottom;
+}
+
+/**
+ *
+ *  Where a piece would land if it were hard dropped now: the lowest
+ *  position straight below it which doesn't collide with the stack
+ *  or with another player's piece.
+ *
+ *  Hard drops never push, so under CollisionPolicy::Push this is
+ *  where a hard drop locks, on top of any piece in the way. A piece
+ *  left to fall may push that piece down and land lower, so as a ghost
+ *  it's only a guide there.
+ *
+ */
+pub fn landing_position(piece : &PieceState,
+                        active_players : &ActivePlayersType,
+                        fallen_blocks : &FallenBlocksType,
+                        board : &Board,
+                        policy : CollisionPolicy) -> Pivot {
+    let mut landed = *piece;
+    loop {
+        let mut below = landed;
+        below.pivot.y += 1;
+        if fallen_blocks_collision(&below, fallen_blocks, board) || player_collision(&below, active_players, policy) {
+            return landed.pivot;
+        }
+        landed = below;
+    }
+}
+
+// Clears any lines necessary, modifying fallen_blocks as appropriate
+// returns the ids of the players who had blocks in each line cleared
+pub fn clear_lines(fallen_blocks : &mut FallenBlocksType, board : &Board) -> Vec<Vec<usize>> {
+    let mut offset = 0;
+    let mut lines_cleared = vec![];
+    for row in (board.top()..board.height).rev() {
+        let mut is_full = true;
+        for col in 0..board.width {
+            let pivot = &Pivot {
+                x: col,
+                y: row,
+            };
+            if !fallen_blocks.contains_key(pivot) {
+                is_full = false;
+                break;
+            }
+        }
+
+        // Need to clear the row if it is full
+        if is_full {
+            offset += 1;
+            let mut contributors = vec![];
+            for col in 0..board.width {
+                let pivot = &Pivot {
+                    x: col,
+                    y: row,
+                };
+                if let Some(block) = fallen_blocks.remove(pivot) {
+                    contributors.push(block.player_id);
+                }
+            }
+            contributors.sort_unstable();
+            contributors.dedup();
+            lines_cleared.push(contributors);
+        } else if offset != 0 {
+            // If did not clear, add to new_fallen_blocks
+            for col in 0..board.width {
+                let pivot = &Pivot {
+                    x: col,
+                    y: row,
+                };
+                let fallen_pivot = Pivot {
+                    x: col,
+                    y: row + offset,
+                };
+
+                match fallen_blocks.get(pivot) {
+                    Some(block) => {
+                        fallen_blocks.insert(fallen_pivot, *block)
+                    },
+                    None => None
+                };
+                fallen_blocks.remove(pivot);
+            }
+        }
+    }
+    return lines_cleared;
+}
+
+/**
+ *
+ *  Checks whether a piece which is about to lock was T-spun into
+ *  place, using the 3-corner rule: the last move must have been a
+ *  rotation of a T, and at least 3 of the 4 corners of its 3x3 box
+ *  must be filled by the stack or the edges of the board.
+ *
+ *  It is a full T-spin if both corners the T points towards are
+ *  filled, or if the rotation needed the last kick test. Otherwise
+ *  it is a mini T-spin.
+ *
+ */
+pub fn detect_t_spin(piece : &PieceState,
+                     fallen_blocks : &FallenBlocksType,
+                     board : &Board) -> Option<TSpin> {
+    if piece.shape != SHAPE_T {
+        return None;
+    }
+    let last_kick = piece.last_kick?;
+
+    let filled = |x : i8, y : i8| {
+        let abs_x = piece.pivot.x + x;
+        let abs_y = piece.pivot.y + y;
+        return !board.in_columns(abs_x) || abs_y >= board.height ||
+            fallen_blocks.contains_key(&Pivot { x: abs_x, y: abs_y });
+    };
+
+    let corners = [(0, 0), (2, 0), (2, 2), (0, 2)];
+    let num_filled = corners.iter().filter(|(x, y)| filled(*x, *y)).count();
+    if num_filled < 3 {
+        return None;
+    }
+
+    // the two corners on the side the T points towards, for each rotation
+    let front_corners = match piece.rotation {
+        0 => [(0, 0), (2, 0)],
+        1 => [(2, 0), (2, 2)],
+        2 => [(2, 2), (0, 2)],
+        _ => [(0, 2), (0, 0)],
+    };
+    let front_filled = front_corners.iter().all(|(x, y)| filled(*x, *y));
+
+    if front_filled || last_kick as usize == NUM_KICKS - 1 {
+        return Some(TSpin::Full);
+    }
+    return Some(TSpin::Mini);
+}
+
+pub fn collision(piece : &PieceState,
+                 active_players: &mut ActivePlayersType,
+                 fallen_blocks : &FallenBlocksType,
+                 board : &Board,
+                 policy : CollisionPolicy) -> bool {
+
+    // if we hit a wall, or were kicked up above the board, return true
+    let screen = screen_collision(piece, board);
+    if screen == CollisionType::Wall || screen == CollisionType::Ceiling { return true; }
+
+    // if we hit a fallen block, return true
+    if fallen_blocks_collision(piece, fallen_blocks, board) {
+        return true;
+    }
+
+    // if we hit another player
+    if player_collision(piece, active_players, policy) {
+        return true;
+    }
+
+    return false;
+}
+
+/**
+ *
+ *  The offset to move a piece by for the given SRS kick test, in
+ *  board coordinates (y pointing down).
+ *
+ */
+pub fn kick_offset(shape : u8, prev_rotation : u8, clockwise : bool, test : usize) -> (i8, i8) {
+    let table = if shape == SHAPE_I { &I_KICKS } else { &JLSTZ_KICKS };
+    let (x, y) = table[prev_rotation as usize][if clockwise {0} else {1}][test];
+    return (x, -y);
+}
+
+/**
+ *
+ *  Tries to rotate a piece using the Super Rotation System. The
+ *  rotated piece (already in new_state) is tested at each of the
+ *  five kick offsets for its transition, and the first one which
+ *  doesn't hit a wall, the stack or another player's piece is used.
+ *
+ *  If none fit, the piece stays in its original rotation. O pieces
+ *  look the same in every rotation, so they never rotate.
+ *
+ */
+fn wallkick(new_state : &mut PieceState,
+            clockwise : bool,
+            active_players : &mut ActivePlayersType,
+            fallen_blocks : &FallenBlocksType,
+            board : &Board,
+            policy : CollisionPolicy) -> PieceState {
+
+    let prev_rotation = if clockwise {
+            (ROT_LIMIT + new_state.rotation - 1) % ROT_LIMIT
+        }
+        else {
+            (new_state.rotation + 1) % ROT_LIMIT
+        };
+
+    // the state this piece was in before trying this rotation
+    let mut prev_state = *new_state;
+    prev_state.rotation = prev_rotation;
+
+    if new_state.shape == SHAPE_O {
+        return prev_state;
+    }
+
+    for test in 0..NUM_KICKS {
+        let (x_test, y_test) = kick_offset(new_state.shape, prev_rotation, clockwise, test);
+
+        let mut kicked_state = *new_state;
+        kicked_state.pivot.x += x_test;
+        kicked_state.pivot.y += y_test;
+        kicked_state.last_kick = Some(test as u8);
+        if !collision(&kicked_state, active_players, fallen_blocks, board, policy) {
+            return kicked_state;
+        }
+    }
+
+    // if we can't rotate or perform a wallkick, return original state
+    return prev_state;
+}
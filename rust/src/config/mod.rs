@@ -0,0 +1,106 @@
+use std::net::SocketAddr;
+
+use serde::Deserialize;
+
+use tetris_engine::config::{Config, ConfigError, Settings, parse};
+
+// the settings which belong to the server rather than the game, see ServerConfig::set
+const SERVER_KEYS : [&str ; 6] = [
+    "bind",
+    "ping_millis",
+    "disconnect_millis",
+    "min_players",
+    "countdown_millis",
+    "game_over_millis",
+];
+
+/**
+ *
+ *  The server's settings, and the settings of the games it runs. Both
+ *  are read from the same TOML file, environment and command line, so
+ *  the file stays flat: the server picks out its own settings and
+ *  leaves the rest to the game's Config.
+ *
+ */
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    // the address the websocket server listens on
+    pub bind: String,
+
+    // how often clients are pinged, and how long they have to answer
+    pub ping_millis: u64,
+    pub disconnect_millis: u64,
+
+    // how many players must be ready before a game counts down, how
+    // long the countdown lasts, and how long the results of a game are
+    // shown before the room waits for players again, see lobby::Lobby
+    pub min_players: usize,
+    pub countdown_millis: u64,
+    pub game_over_millis: u64,
+
+    // the settings every room's game starts from
+    #[serde(skip)]
+    pub game: Config,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            bind: "0.0.0.0:3012".to_string(),
+            ping_millis: 1000, // 1 second
+            disconnect_millis: 3000, // 3 seconds
+            min_players: 1,
+            countdown_millis: 3000, // 3 seconds
+            game_over_millis: 5000, // 5 seconds
+            game: Config::default(),
+        }
+    }
+}
+
+impl Settings for ServerConfig {
+    fn keys() -> Vec<&'static str> {
+        return SERVER_KEYS.iter().chain(Config::keys().iter()).copied().collect();
+    }
+
+    fn from_toml(contents: &str) -> Result<ServerConfig, ConfigError> {
+        let mut game : toml::value::Table = toml::from_str(contents).map_err(|e| ConfigError(e.to_string()))?;
+
+        let server : toml::value::Table = SERVER_KEYS.iter()
+            .filter_map(|key| game.remove(*key).map(|value| (key.to_string(), value)))
+            .collect();
+
+        let mut config : ServerConfig = toml::Value::Table(server).try_into()
+            .map_err(|e: toml::de::Error| ConfigError(e.to_string()))?;
+        config.game = toml::Value::Table(game).try_into()
+            .map_err(|e: toml::de::Error| ConfigError(e.to_string()))?;
+        return Ok(config);
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "bind" => self.bind = value.to_string(),
+            "ping_millis" => self.ping_millis = parse(value)?,
+            "disconnect_millis" => self.disconnect_millis = parse(value)?,
+            "min_players" => self.min_players = parse(value)?,
+            "countdown_millis" => self.countdown_millis = parse(value)?,
+            "game_over_millis" => self.game_over_millis = parse(value)?,
+            _ => return self.game.set(key, value),
+        }
+        return Ok(());
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let check = |ok: bool, message: String| {
+            return if ok { Ok(()) } else { Err(ConfigError(message)) };
+        };
+
+        check(self.bind.parse::<SocketAddr>().is_ok(),
+              format!("bind must be an address and port like 0.0.0.0:3012, got {:?}", self.bind))?;
+        check(self.ping_millis > 0 && self.ping_millis < self.disconnect_millis,
+              format!("ping_millis must be positive and less than disconnect_millis ({}), got {}",
+                      self.disconnect_millis, self.ping_millis))?;
+        check(self.min_players > 0, "min_players must be at least 1".to_string())?;
+        return self.game.validate();
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeSet;
+
+use serde_json::{json, Value};
+
+use crate::config::ServerConfig;
+
+/**
+ *
+ *  Where a room is between games. Pieces only fall while Playing.
+ *
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    // until there are enough players and they are all ready
+    Waiting,
+
+    // the game starts at ends_at, unless a player who was there when it
+    // started stops being ready or leaves
+    Countdown {
+        ends_at: u128,
+    },
+
+    Playing,
+
+    // the results of the last game are shown until ends_at
+    GameOver {
+        ends_at: u128,
+    },
+}
+
+impl Phase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Waiting => "waiting",
+            Phase::Countdown { .. } => "countdown",
+            Phase::Playing => "playing",
+            Phase::GameOver { .. } => "gameOver",
+        }
+    }
+}
+
+/**
+ *
+ *  Decides when a room's games start and stop. Owned by the room's
+ *  game thread, which tells it who is playing and when a game ends,
+ *  and announces every change of phase to the room.
+ *
+ *  Times are in milliseconds on the room's clock.
+ *
+ */
+pub struct Lobby {
+    phase: Phase,
+
+    // the players who are ready for the next game
+    ready: BTreeSet<usize>,
+
+    // the players the countdown is waiting on, so that someone joining
+    // part way through it can't hold it up by not being ready yet
+    counting_down: BTreeSet<usize>,
+
+    min_players: usize,
+    countdown_millis: u64,
+    game_over_millis: u64,
+}
+
+impl Lobby {
+    pub fn new(config: &ServerConfig) -> Lobby {
+        Lobby {
+            phase: Phase::Waiting,
+            ready: BTreeSet::new(),
+            counting_down: BTreeSet::new(),
+            min_players: config.min_players,
+            countdown_millis: config.countdown_millis,
+            game_over_millis: config.game_over_millis,
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        return self.phase;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        return self.phase == Phase::Playing;
+    }
+
+    // returns true if the player's readiness changed
+    pub fn set_ready(&mut self, player_id: usize, ready: bool) -> bool {
+        if ready {
+            return self.ready.insert(player_id);
+        }
+        return self.ready.remove(&player_id);
+    }
+
+    // forgets a player who left or started spectating, returns true if they were ready
+    pub fn remove(&mut self, player_id: usize) -> bool {
+        return self.ready.remove(&player_id);
+    }
+
+    /**
+     *
+     *  Moves on to the next phase if it is time to, given the players
+     *  in the game. Returns true if the phase changed. Players who join
+     *  during a countdown don't have to be ready for it, they just play.
+     *
+     *  A game which everyone has left goes back to waiting, and the
+     *  caller should start a new one.
+     *
+     */
+    pub fn update(&mut self, players: &[usize], now: u128) -> bool {
+        let counted : Vec<&usize> = match self.phase {
+            Phase::Countdown { .. } => players.iter().filter(|player_id| self.counting_down.contains(player_id)).collect(),
+            _ => players.iter().collect(),
+        };
+        let all_ready = counted.len() >= self.min_players &&
+            counted.iter().all(|player_id| self.ready.contains(player_id));
+
+        let next = match self.phase {
+            Phase::Waiting if all_ready => {
+                self.counting_down = players.iter().copied().collect();
+                Phase::Countdown {
+                    ends_at: now + self.countdown_millis as u128,
+                }
+            },
+            Phase::Countdown { .. } if !all_ready => Phase::Waiting,
+            Phase::Countdown { ends_at } if now >= ends_at => Phase::Playing,
+            Phase::Playing if players.is_empty() => Phase::Waiting,
+            Phase::GameOver { ends_at } if now >= ends_at => Phase::Waiting,
+            phase => phase,
+        };
+
+        if next == self.phase {
+            return false;
+        }
+        self.phase = next;
+        return true;
+    }
+
+    // the game ended, so everyone has to ready up again for the next one
+    pub fn game_over(&mut self, now: u128) {
+        self.phase = Phase::GameOver {
+            ends_at: now + self.game_over_millis as u128,
+        };
+        self.ready.clear();
+    }
+
+    /**
+     *
+     *  The lobby message sent to the room whenever the phase or anyone's
+     *  readiness changes, and to players when they join.
+     *
+     */
+    pub fn to_message(&self, players: &[usize], now: u128) -> Value {
+        let mut message = json!({
+            "type": "lobby",
+            "phase": self.phase.name(),
+            "players": players,
+            "ready": self.ready.iter().filter(|player_id| players.contains(player_id)).collect::<Vec<_>>(),
+            "min_players": self.min_players,
+        });
+
+        match self.phase {
+            Phase::Countdown { ends_at } | Phase::GameOver { ends_at } => {
+                message["remaining_millis"] = json!(ends_at.saturating_sub(now) as u64);
+            },
+            _ => {},
+        }
+        return message;
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use rand::{thread_rng, Rng};
+use serde_json::Value;
+use ws::Sender;
+
+use tetris_engine::board::{WIDTH_RANGE, HEIGHT_RANGE, BUFFER_RANGE};
+use tetris_engine::clock::Clock;
+use tetris_engine::config::Config;
+use tetris_engine::game::Game;
+use tetris_engine::handling::HandlingUpdate;
+use tetris_engine::input::{Input, Role};
+use tetris_engine::randomizer::RandomizerKind;
+use tetris_engine::tetris::CollisionPolicy;
+
+use crate::config::ServerConfig;
+use crate::game_frame;
+use crate::lobby::Lobby;
+use crate::wire::{Encoding, OutgoingMessage};
+
+pub const DEFAULT_ROOM : &str = "default";
+
+// the handshake path prefix which selects a room, e.g. /room/<name>
+const ROOM_PATH_PREFIX : &str = "/room/";
+
+const MAX_ROOM_NAME_LEN : usize = 32;
+
+/**
+ *
+ *  Everything a connection can ask of a room's game thread, which
+ *  owns the game and acts on these in the order they were sent.
+ *
+ */
+pub enum RoomCommand {
+    // a connection joined, and should be sent the init message and a snapshot
+    Join {
+        player_id: usize,
+        out: Sender,
+        encoding: Encoding,
+        role: Role,
+    },
+
+    // a connection switched between playing and spectating
+    SetRole {
+        player_id: usize,
+        role: Role,
+    },
+
+    Leave {
+        player_id: usize,
+    },
+
+    // a player is, or is no longer, ready for the next game
+    Ready {
+        player_id: usize,
+        ready: bool,
+    },
+
+    // a player's input, applied on the next tick
+    Input(Input),
+
+    // change how a player's held keys repeat
+    Handling {
+        player_id: usize,
+        update: HandlingUpdate,
+    },
+
+    // send this player a full snapshot of the game on the next frame
+    Resync {
+        player_id: usize,
+    },
+
+    // the last member left, so the game thread should exit
+    Close,
+}
+
+/**
+ *
+ *  A single, independent game. Every room runs its own game_frame
+ *  thread, which owns the game state, and state updates are only
+ *  sent to the members of the room.
+ *
+ */
+pub struct Room {
+    pub name: String,
+
+    // the way in to the room's game thread
+    commands: mpsc::Sender<RoomCommand>,
+
+    // how many connections are in the room, only changed with the registry locked
+    member_count: AtomicUsize,
+}
+
+impl Room {
+    pub fn send(&self, command: RoomCommand) {
+        if self.commands.send(command).is_err() {
+            println!("Room {} is no longer running", self.name);
+        }
+    }
+}
+
+/**
+ *
+ *  The connections in a room, and the encoding each of them asked
+ *  for, keyed by player id. Owned by the room's game thread.
+ *
+ */
+#[derive(Default)]
+pub struct Members {
+    connections: HashMap<usize, (Sender, Encoding)>,
+}
+
+impl Members {
+    pub fn insert(&mut self, player_id: usize, out: Sender, encoding: Encoding) {
+        self.connections.insert(player_id, (out, encoding));
+    }
+
+    pub fn remove(&mut self, player_id: usize) {
+        self.connections.remove(&player_id);
+    }
+
+    /**
+     *
+     *  Sends a message to every connection in the room. Each Sender
+     *  is a channel into the websocket event loop, so this never waits
+     *  on the clients.
+     *
+     */
+    pub fn broadcast(&self, message: &Value) {
+        let mut message = OutgoingMessage::new(message);
+        for (player_id, (out, encoding)) in self.connections.iter() {
+            if let Err(e) = out.send(message.encoded(*encoding)) {
+                println!("Unable to broadcast info to {}: {}", player_id, e);
+            }
+        }
+    }
+
+    /**
+     *
+     *  Sends a message to a single connection in the room.
+     *
+     */
+    pub fn send_to(&self, player_id: usize, message: &Value) {
+        if let Some((out, encoding)) = self.connections.get(&player_id) {
+            if let Err(e) = out.send(encoding.encode(message)) {
+                println!("Unable to send info to {}: {}", player_id, e);
+            }
+        }
+    }
+}
+
+/**
+ *
+ *  Keeps track of every room on the server. Rooms are created when
+ *  the first player joins them and closed when the last one leaves.
+ *
+ */
+pub struct RoomRegistry {
+    rooms: Mutex<HashMap<String, Arc<Room>>>,
+
+    // the settings for rooms which don't ask for their own
+    config: ServerConfig,
+
+    // shared by every room
+    clock: Arc<dyn Clock>,
+}
+
+impl RoomRegistry {
+    pub fn new(config: ServerConfig, clock: Arc<dyn Clock>) -> RoomRegistry {
+        RoomRegistry {
+            rooms: Mutex::new(HashMap::new()),
+            config,
+            clock,
+        }
+    }
+
+    /**
+     *
+     *  Adds a connection to the room named in options, creating the
+     *  room and starting its game thread if it doesn't exist yet.
+     *
+     *  The rest of the options only apply when the room is created.
+     *
+     */
+    pub fn join(&self, options: &RoomOptions, player_id: usize, out: Sender, encoding: Encoding) -> Arc<Room> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let name = &options.name;
+
+        let room = match rooms.get(name) {
+            Some(room) => room.clone(),
+            None => {
+                let config = options.apply(&self.config.game);
+                let seed = config.seed.unwrap_or_else(|| thread_rng().gen());
+                println!("Creating room {} ({} randomizer, {}x{} board, seed {})",
+                         name, config.randomizer, config.board.width, config.board.height, seed);
+
+                let (commands, receiver) = mpsc::channel();
+                let room = Arc::new(Room {
+                    name: name.to_string(),
+                    commands,
+                    member_count: AtomicUsize::new(0),
+                });
+                rooms.insert(name.to_string(), room.clone());
+
+                let game = Game::new(config, seed);
+                let lobby = Lobby::new(&self.config);
+                let thread_name = name.to_string();
+                let clock = self.clock.clone();
+                thread::spawn(move || {
+                    game_frame(thread_name, game, lobby, clock, receiver);
+                });
+                room
+            },
+        };
+
+        room.member_count.fetch_add(1, Ordering::SeqCst);
+        room.send(RoomCommand::Join { player_id, out, encoding, role: options.role });
+        return room;
+    }
+
+    /**
+     *
+     *  Removes a connection from a room. If the room is left empty
+     *  it is removed from the registry and its game thread stops.
+     *
+     */
+    pub fn leave(&self, room: &Room, player_id: usize) {
+        let mut rooms = self.rooms.lock().unwrap();
+        room.send(RoomCommand::Leave { player_id });
+
+        if room.member_count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            println!("Closing room {}", room.name);
+            room.send(RoomCommand::Close);
+            rooms.remove(&room.name);
+        }
+    }
+}
+
+/**
+ *
+ *  The room a connection asked to join, whether it wants to play or
+ *  spectate, and the settings to create the room with if it doesn't
+ *  exist yet, read from the handshake resource, e.g.
+ *  "/room/<name>?randomizer=tgm&width=10&height=20&collision=push".
+ *
+ */
+#[derive(Debug, Default, PartialEq)]
+pub struct RoomOptions {
+    pub name: String,
+    pub randomizer: Option<RandomizerKind>,
+    pub width: Option<i8>,
+    pub height: Option<i8>,
+    pub buffer: Option<i8>,
+    pub collision: Option<CollisionPolicy>,
+    pub role: Role,
+}
+
+impl RoomOptions {
+    pub fn from_resource(resource: &str) -> RoomOptions {
+        RoomOptions {
+            name: room_name(resource),
+            randomizer: parsed_param(resource, "randomizer"),
+            width: dimension_param(resource, "width", WIDTH_RANGE),
+            height: dimension_param(resource, "height", HEIGHT_RANGE),
+            buffer: dimension_param(resource, "buffer", BUFFER_RANGE),
+            collision: parsed_param(resource, "collision"),
+            role: parsed_param(resource, "role").unwrap_or_default(),
+        }
+    }
+
+    // the configuration for a new room with these options
+    pub fn apply(&self, config: &Config) -> Config {
+        let mut config = config.clone();
+        config.randomizer = self.randomizer.unwrap_or(config.randomizer);
+        config.board.width = self.width.unwrap_or(config.board.width);
+        config.board.height = self.height.unwrap_or(config.board.height);
+        config.board.buffer = self.buffer.unwrap_or(config.board.buffer);
+        config.collision = self.collision.unwrap_or(config.collision);
+        return config;
+    }
+}
+
+/**
+ *
+ *  Reads a setting such as the randomizer from the query string of a
+ *  handshake resource, ignoring it if it isn't one we know.
+ *
+ */
+fn parsed_param<T: FromStr<Err = String>>(resource: &str, key: &str) -> Option<T> {
+    match query_param(resource, key)?.parse::<T>() {
+        Ok(value) => return Some(value),
+        Err(e) => {
+            println!("Ignoring requested {}: {}", key, e);
+            return None;
+        },
+    }
+}
+
+/**
+ *
+ *  Reads a board dimension from the query string of a handshake
+ *  resource, ignoring it if it isn't a number in range.
+ *
+ */
+fn dimension_param(resource: &str, key: &str, range: RangeInclusive<i8>) -> Option<i8> {
+    let value = query_param(resource, key)?;
+
+    match value.parse::<i8>() {
+        Ok(size) if range.contains(&size) => return Some(size),
+        _ => {
+            println!("Ignoring requested {} {:?}, expected {} to {}", key, value, range.start(), range.end());
+            return None;
+        },
+    }
+}
+
+/**
+ *
+ *  Reads the value of key from the query string of a handshake
+ *  resource, e.g. "/room/blue?key=value".
+ *
+ */
+fn query_param<'a>(resource: &'a str, key: &str) -> Option<&'a str> {
+    let query = resource.split('?').nth(1)?;
+
+    for pair in query.split('&') {
+        let mut parts = pair.splitn(2, '=');
+        if parts.next() == Some(key) {
+            return Some(parts.next().unwrap_or(""));
+        }
+    }
+
+    return None;
+}
+
+/**
+ *
+ *  Reads the room name out of a handshake resource such as
+ *  "/room/<name>?foo=bar". Connections which don't request a room,
+ *  or request one with an invalid name, play in the default room.
+ *
+ */
+pub fn room_name(resource: &str) -> String {
+    // ignore any query string
+    let path = resource.split('?').next().unwrap_or("");
+
+    if let Some(name) = path.strip_prefix(ROOM_PATH_PREFIX) {
+        let name = name.trim_end_matches('/');
+        let valid = !name.is_empty() && name.len() <= MAX_ROOM_NAME_LEN &&
+            name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        if valid {
+            return name.to_string();
+        }
+    }
+
+    return DEFAULT_ROOM.to_string();
+}
@@ -1,8 +1,15 @@
-
-
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
-    use crate::next_piece;
+    use crate::config::ServerConfig;
+    use crate::lobby::{Lobby, Phase};
+    use crate::room::{room_name, RoomOptions, DEFAULT_ROOM};
+    use crate::run;
+    use crate::wire::{Encoding, decode, JSON_PROTOCOL, MSGPACK_PROTOCOL};
+    use tetris_engine::config::{Config, Settings};
+    use tetris_engine::input::{KeyState, ControlMessage, Role};
+    use tetris_engine::randomizer::RandomizerKind;
+    use tetris_engine::tetris::CollisionPolicy;
     use websocket::ClientBuilder;
 
     // support synchronous websockets, great for testing
@@ -13,12 +20,10 @@ mod tests {
     static INIT_SERVER : Once = Once::new();
 
     fn start_server() {
-        use crate::main as program_main;
-
         // this is synchronous, so we'll wait the 1 second before returning
         INIT_SERVER.call_once(|| {
             thread::spawn(move || {
-                program_main();
+                run(ServerConfig::default());
             });
 
             thread::sleep(time::Duration::from_millis(1000));
@@ -26,9 +31,169 @@ mod tests {
     }
 
     #[test]
-    fn test_next_piece() {
-        assert!(next_piece() >= 0);
-        assert!(next_piece() <= 7);
+    fn test_room_options() {
+        let options = RoomOptions::from_resource("/room/blue?randomizer=tgm");
+        assert_eq!(options.name, "blue");
+        assert_eq!(options.randomizer, Some(RandomizerKind::TgmHistory));
+
+        assert_eq!(RoomOptions::from_resource("/?randomizer=7bag").randomizer, Some(RandomizerKind::SevenBag));
+        assert_eq!(RoomOptions::from_resource("/room/blue?randomizer=bogus").randomizer, None);
+        assert_eq!(RoomOptions::from_resource("/room/blue").randomizer, None);
+
+        let options = RoomOptions::from_resource("/room/tall?width=10&height=30&buffer=0");
+        assert_eq!((options.width, options.height, options.buffer), (Some(10), Some(30), Some(0)));
+
+        let options = RoomOptions::from_resource("/room/blue?collision=push");
+        assert_eq!(options.collision, Some(CollisionPolicy::Push));
+        assert_eq!(options.apply(&Config::default()).collision, CollisionPolicy::Push);
+        assert_eq!(RoomOptions::from_resource("/room/blue?collision=bogus").collision, None);
+
+        assert_eq!(RoomOptions::from_resource("/room/blue?role=spectator").role, Role::Spectator);
+        assert_eq!(RoomOptions::from_resource("/room/blue?role=bogus").role, Role::Player);
+        assert_eq!(RoomOptions::from_resource("/room/blue").role, Role::Player);
+
+        // sizes out of range are ignored
+        let options = RoomOptions::from_resource("/room/tall?width=4&height=300&buffer=-1");
+        assert_eq!((options.width, options.height, options.buffer), (None, None, None));
+    }
+
+    #[test]
+    fn test_room_name() {
+        assert_eq!(room_name("/room/blue"), "blue");
+        assert_eq!(room_name("/room/blue/"), "blue");
+        assert_eq!(room_name("/room/team-1_a?x=1"), "team-1_a");
+        assert_eq!(room_name("/"), DEFAULT_ROOM);
+        assert_eq!(room_name("/room/"), DEFAULT_ROOM);
+        assert_eq!(room_name("/room/bad name"), DEFAULT_ROOM);
+        assert_eq!(room_name("/lobby"), DEFAULT_ROOM);
+    }
+
+    #[test]
+    fn test_wire_encoding() {
+        assert_eq!(Encoding::from_protocols(&["chat", MSGPACK_PROTOCOL]), Some(Encoding::MsgPack));
+        assert_eq!(Encoding::from_protocols(&[JSON_PROTOCOL, MSGPACK_PROTOCOL]), Some(Encoding::Json));
+        assert_eq!(Encoding::from_protocols(&["chat"]), None);
+
+        // the same input parses from both JSON text and MessagePack binary frames
+        let input = serde_json::json!({
+            "left": true, "right": false, "rot": false, "counter_rot": true,
+            "hard_drop": false, "fast_drop": false, "player_id": 0, "player_name": "bob",
+        });
+        for encoding in [Encoding::Json, Encoding::MsgPack].iter() {
+            let key_state : KeyState = decode(&encoding.encode(&input)).unwrap();
+            assert!(key_state.left && key_state.counter_rot && !key_state.right);
+            assert_eq!(key_state.player_name, "bob");
+        }
+
+        let resync = serde_json::json!({ "type": "resync" });
+        assert_eq!(decode::<ControlMessage>(&Encoding::MsgPack.encode(&resync)).unwrap(),
+                   ControlMessage::Resync);
+
+        let join = serde_json::json!({ "type": "join", "role": "spectator" });
+        assert_eq!(decode::<ControlMessage>(&Encoding::Json.encode(&join)).unwrap(),
+                   ControlMessage::Join { role: Role::Spectator });
+
+        // known keys are sent as their index, and anything else by name
+        let delta = serde_json::json!({
+            "type": "gameDelta", "seq": 7,
+            "moved_pieces": [{ "player_id": 1, "pivot": { "x": 4, "y": -2 }, "ghost": null }],
+            "input_frames": { "1": 12 },
+            "stats": [{ "apm": 12.5 }],
+        });
+        let binary = match Encoding::MsgPack.encode(&delta) {
+            ws::Message::Binary(data) => data,
+            _ => panic!("Expected a binary message."),
+        };
+        assert!(!binary.windows(5).any(|window| window == b"pivot"));
+        assert!(binary.len() < delta.to_string().len() / 2);
+        assert_eq!(decode::<serde_json::Value>(&ws::Message::binary(binary)).unwrap(), delta);
+    }
+
+    #[test]
+    fn test_server_config() {
+        let config = ServerConfig::from_toml(r#"
+            bind = "127.0.0.1:4000"
+            frame_millis = 10
+
+            [board]
+            width = 12
+        "#).unwrap();
+        assert_eq!(config.bind, "127.0.0.1:4000");
+        assert_eq!(config.ping_millis, ServerConfig::default().ping_millis);
+        assert_eq!(config.game.frame_millis, 10);
+        assert_eq!(config.game.board.width, 12);
+        assert!(config.validate().is_ok());
+
+        // typos in either half are still errors
+        assert!(ServerConfig::from_toml("bnid = \"127.0.0.1:4000\"").is_err());
+        assert!(ServerConfig::from_toml("ping_millis = \"often\"").is_err());
+
+        let load = |args: &[&str]| ServerConfig::load(args.iter().map(|arg| arg.to_string()));
+        let config = load(&["--ping-millis", "500", "--min-players", "2", "--frame-millis", "20"]).unwrap();
+        assert_eq!((config.ping_millis, config.min_players, config.game.frame_millis), (500, 2, 20));
+
+        assert!(load(&["--colour", "red"]).is_err());
+        assert!(load(&["--bind", "localhost"]).is_err());
+        assert!(load(&["--ping-millis", "5000"]).is_err());
+        assert!(load(&["--min-players", "0"]).is_err());
+        assert!(load(&["--width", "4"]).is_err());
+    }
+
+    #[test]
+    fn test_lobby() {
+        let config = ServerConfig { min_players: 2, countdown_millis: 3000, game_over_millis: 5000, ..ServerConfig::default() };
+        let mut lobby = Lobby::new(&config);
+        assert_eq!(lobby.phase(), Phase::Waiting);
+
+        // not enough players, however ready they are
+        assert!(lobby.set_ready(1, true));
+        assert!(!lobby.set_ready(1, true));
+        assert!(!lobby.update(&[1], 0));
+
+        // everyone has to be ready
+        assert!(!lobby.update(&[1, 2], 0));
+        lobby.set_ready(2, true);
+        assert!(lobby.update(&[1, 2], 100));
+        assert_eq!(lobby.phase(), Phase::Countdown { ends_at: 3100 });
+        assert_eq!(lobby.to_message(&[1, 2], 1100)["remaining_millis"], 2000);
+
+        // the countdown stops if anyone stops being ready
+        lobby.set_ready(2, false);
+        assert!(lobby.update(&[1, 2], 200));
+        assert_eq!(lobby.phase(), Phase::Waiting);
+        lobby.set_ready(2, true);
+        lobby.update(&[1, 2], 300);
+
+        // someone joining part way through doesn't stop it
+        assert!(!lobby.update(&[1, 2, 3], 1000));
+        assert_eq!(lobby.phase(), Phase::Countdown { ends_at: 3300 });
+
+        // or the game starts once it is over, the newcomer playing too
+        assert!(!lobby.update(&[1, 2, 3], 3299));
+        assert!(lobby.update(&[1, 2, 3], 3300));
+        assert!(lobby.is_playing());
+        let message = lobby.to_message(&[1, 2], 3300);
+        assert_eq!(message["phase"], "playing");
+        assert_eq!(message["ready"], serde_json::json!([1, 2]));
+
+        // after a game, the results are shown and everyone has to ready up again
+        lobby.game_over(10_000);
+        assert_eq!(lobby.to_message(&[1, 2], 10_000)["phase"], "gameOver");
+        assert!(!lobby.update(&[1, 2], 14_999));
+        assert!(lobby.update(&[1, 2], 15_000));
+        assert_eq!(lobby.phase(), Phase::Waiting);
+        assert!(!lobby.update(&[1, 2], 20_000));
+
+        // a game everyone leaves goes back to waiting
+        lobby.set_ready(1, true);
+        lobby.set_ready(2, true);
+        lobby.update(&[1, 2], 20_000);
+        lobby.update(&[1, 2], 23_000);
+        assert!(lobby.is_playing());
+        assert!(lobby.remove(1));
+        assert!(!lobby.update(&[2], 23_100));
+        assert!(lobby.update(&[], 23_200));
+        assert_eq!(lobby.phase(), Phase::Waiting);
     }
 
     /*
@@ -37,7 +202,7 @@ mod tests {
     #[test]
     fn test_ws_init_flow() {
         use websocket::message::OwnedMessage;
-        use serde_json::{Result, Error, Value};
+        use serde_json::Value;
 
 
         start_server();
@@ -52,7 +217,7 @@ mod tests {
         // assert that a message was received
         let message_string = match msg {
             OwnedMessage::Text(text) => text,
-            WebSocketError => panic!("Can't read message."),
+            _ => panic!("Can't read message."),
         };
 
         // Parse the string of data into serde_json::Value.
@@ -61,17 +226,119 @@ mod tests {
         // check to make sure message_type is correct
         assert!(message_json["type"] == "init");
 
-        // check to make sure piece_type is in correct range
-        assert!(message_json["piece_type"].is_number());
-        let piece_type : i64 = message_json["piece_type"].as_i64().unwrap();
-        assert!(piece_type >= 0);
-        assert!(piece_type <= 6);
+        // check to make sure we were put in the default room
+        assert!(message_json["room"] == "default");
 
         // check to make sure we have a non-zero user id
         assert!(message_json["player_id"].is_number());
         let user_id = message_json["player_id"].as_i64().unwrap();
         assert!(user_id >= 0);
     }
+
+    // the next text message from the server, anything else reads as an empty object
+    fn recv_json(client: &mut websocket::sync::Client<std::net::TcpStream>) -> serde_json::Value {
+        use websocket::message::OwnedMessage;
+
+        match client.recv_message().unwrap() {
+            OwnedMessage::Text(text) => return serde_json::from_str(&text).unwrap(),
+            _ => return serde_json::json!({}),
+        }
+    }
+
+    /*
+    Test to make sure that the room's game thread answers joins and resyncs.
+    */
+    #[test]
+    fn test_ws_resync_flow() {
+        use websocket::message::OwnedMessage;
+        use serde_json::json;
+
+        start_server();
+
+        let mut client = ClientBuilder::new("ws://127.0.0.1:3012/room/resync")
+            .unwrap()
+            .connect_insecure()
+            .unwrap();
+
+        // joining is answered with the init message, then the full state
+        // (after the delta for this frame, which it includes)
+        let init = recv_json(&mut client);
+        assert!(init["type"] == "init");
+        let snapshot = (0..10).map(|_| recv_json(&mut client)).find(|message| message["type"] == "gameState").unwrap();
+        assert!(snapshot["seed"] == init["seed"]);
+
+        // a resync is answered with the full state again, after any deltas already on the way
+        let resync = OwnedMessage::Text(json!({ "type": "resync" }).to_string());
+        client.send_message(&resync).unwrap();
+        let resynced = (0..100).map(|_| recv_json(&mut client)).find(|message| message["type"] == "gameState");
+        assert!(resynced.is_some());
+    }
+
+    /*
+    Test to make sure that spectators are sent the game but don't join the queue
+    until they ask to play.
+    */
+    #[test]
+    fn test_ws_spectator_flow() {
+        use websocket::message::OwnedMessage;
+        use serde_json::json;
+
+        start_server();
+
+        let mut client = ClientBuilder::new("ws://127.0.0.1:3012/room/spectate?role=spectator")
+            .unwrap()
+            .connect_insecure()
+            .unwrap();
+
+        let init = recv_json(&mut client);
+        assert!(init["type"] == "init");
+        assert!(init["role"] == "spectator");
+        let player_id = init["player_id"].clone();
+
+        let snapshot = (0..10).map(|_| recv_json(&mut client)).find(|message| message["type"] == "gameState").unwrap();
+        assert!(snapshot["player_queue"].as_array().unwrap().is_empty());
+        assert!(snapshot["piece_states"].as_array().unwrap().is_empty());
+
+        // asking to play is confirmed, and puts the player in the game
+        let join = OwnedMessage::Text(json!({ "type": "join", "role": "player" }).to_string());
+        client.send_message(&join).unwrap();
+        let role = (0..100).map(|_| recv_json(&mut client)).find(|message| message["type"] == "role").unwrap();
+        assert!(role["role"] == "player");
+
+        let playing = (0..100).map(|_| recv_json(&mut client)).find(|message| {
+            let in_queue = message["player_queue"].as_array().is_some_and(|queue| queue.contains(&player_id));
+            let in_play = message["moved_pieces"].as_array()
+                .is_some_and(|pieces| pieces.iter().any(|piece| piece["player_id"] == player_id));
+            return in_queue || in_play;
+        });
+        assert!(playing.is_some());
+    }
+
+    /*
+    Test to make sure that clients asking for MessagePack get binary frames.
+    */
+    #[test]
+    fn test_ws_msgpack_init_flow() {
+        use websocket::message::OwnedMessage;
+        use serde_json::Value;
+
+        start_server();
+
+        let mut client = ClientBuilder::new("ws://127.0.0.1:3012/room/msgpack")
+            .unwrap()
+            .add_protocol(MSGPACK_PROTOCOL)
+            .connect_insecure()
+            .unwrap();
+
+        let message_json : Value = match client.recv_message().unwrap() {
+            OwnedMessage::Binary(data) => decode(&ws::Message::binary(data)).unwrap(),
+            _ => panic!("Expected a binary message."),
+        };
+
+        assert!(message_json["type"] == "init");
+        assert!(message_json["room"] == "msgpack");
+    }
+
 }
 
 
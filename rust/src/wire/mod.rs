@@ -0,0 +1,240 @@
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{DeserializeOwned, MapAccess, SeqAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde_json::{Map, Value};
+use ws::Message;
+
+// subprotocols a client can ask for in the Sec-WebSocket-Protocol header
+pub const JSON_PROTOCOL : &str = "tetris.json";
+pub const MSGPACK_PROTOCOL : &str = "tetris.msgpack";
+
+// MessagePack frames send these map keys as their index in this list, so
+// that the names aren't repeated in every frame. Clients keep the same
+// list (see js/wire.js), so new keys only ever go on the end.
+pub const KEYS : &[&str] = &[
+    // gameState and gameDelta
+    "type", "version", "seq", "piece_states", "fallen_blocks", "player_queue",
+    "piece_queue", "score", "seed", "board", "stats", "input_frames",
+    "added_blocks", "removed_blocks", "moved_pieces", "removed_pieces", "events",
+    // pieces and blocks
+    "shape", "pivot", "rotation", "player_id", "player_name", "ghost", "x", "y",
+    "original_shape", "position", "width", "height", "buffer",
+    // stats and events
+    "pieces_placed", "lines_cleared", "tetrises", "hard_drops", "points",
+    "actions", "apm", "event", "playerId", "kind", "lines", "count",
+    // init, role and lobby
+    "room", "randomizer", "collision", "role", "phase", "players", "ready",
+    "min_players", "remaining_millis",
+    // inputs and other messages from clients
+    "left", "right", "rot", "counter_rot", "hard_drop", "fast_drop", "hold",
+    "frame", "key", "pressed", "das_millis", "arr_millis", "soft_drop_millis",
+];
+
+/**
+ *
+ *  How messages sent to a connection are encoded. JSON text is the
+ *  default; clients which ask for the msgpack subprotocol are sent
+ *  MessagePack binary frames instead, with the keys in KEYS sent as
+ *  their index, which are much smaller.
+ *
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Json,
+    MsgPack,
+}
+
+impl Encoding {
+    /**
+     *
+     *  Picks the encoding for the first subprotocol requested by the
+     *  client that we understand.
+     *
+     */
+    pub fn from_protocols(protocols: &[&str]) -> Option<Encoding> {
+        for protocol in protocols {
+            match *protocol {
+                JSON_PROTOCOL => return Some(Encoding::Json),
+                MSGPACK_PROTOCOL => return Some(Encoding::MsgPack),
+                _ => {},
+            }
+        }
+        return None;
+    }
+
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            Encoding::Json => JSON_PROTOCOL,
+            Encoding::MsgPack => MSGPACK_PROTOCOL,
+        }
+    }
+
+    pub fn encode(&self, message: &Value) -> Message {
+        match self {
+            Encoding::Json => Message::text(message.to_string()),
+            Encoding::MsgPack => Message::binary(rmp_serde::to_vec(&Compact(message)).unwrap()),
+        }
+    }
+}
+
+/**
+ *
+ *  A message which is encoded at most once per encoding, no matter
+ *  how many connections it is sent to.
+ *
+ */
+pub struct OutgoingMessage<'a> {
+    value: &'a Value,
+    json: Option<Message>,
+    msgpack: Option<Message>,
+}
+
+impl<'a> OutgoingMessage<'a> {
+    pub fn new(value: &'a Value) -> OutgoingMessage<'a> {
+        OutgoingMessage {
+            value,
+            json: None,
+            msgpack: None,
+        }
+    }
+
+    pub fn encoded(&mut self, encoding: Encoding) -> Message {
+        let value = self.value;
+        let cached = match encoding {
+            Encoding::Json => &mut self.json,
+            Encoding::MsgPack => &mut self.msgpack,
+        };
+        return cached.get_or_insert_with(|| encoding.encode(value)).clone();
+    }
+}
+
+/**
+ *
+ *  Parses an incoming message. Text frames are read as JSON and
+ *  binary frames as MessagePack, whichever encoding was negotiated.
+ *  Keys in binary frames may be sent either by name or by their
+ *  index in KEYS.
+ *
+ */
+pub fn decode<T: DeserializeOwned>(msg: &Message) -> Result<T, String> {
+    match msg {
+        Message::Text(text) => serde_json::from_str(text).map_err(|e| e.to_string()),
+        Message::Binary(data) => {
+            let expanded : Expanded = rmp_serde::from_slice(data).map_err(|e| e.to_string())?;
+            serde_json::from_value(expanded.0).map_err(|e| e.to_string())
+        },
+    }
+}
+
+// a message to be written with the keys in KEYS replaced by their index
+struct Compact<'a>(&'a Value);
+
+impl Serialize for Compact<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Array(values) => serializer.collect_seq(values.iter().map(Compact)),
+            Value::Object(map) => {
+                let mut entries = serializer.serialize_map(Some(map.len()))?;
+                for (key, value) in map {
+                    match KEYS.iter().position(|known| known == key) {
+                        Some(index) => entries.serialize_entry(&(index as u8), &Compact(value))?,
+                        None => entries.serialize_entry(key, &Compact(value))?,
+                    }
+                }
+                return entries.end();
+            },
+            value => value.serialize(serializer),
+        }
+    }
+}
+
+// a message read with any key indexes turned back into names
+struct Expanded(Value);
+
+impl<'de> Deserialize<'de> for Expanded {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Expanded, D::Error> {
+        return deserializer.deserialize_any(ExpandedVisitor);
+    }
+}
+
+struct ExpandedVisitor;
+
+impl<'de> Visitor<'de> for ExpandedVisitor {
+    type Value = Expanded;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a message")
+    }
+
+    fn visit_unit<E>(self) -> Result<Expanded, E> {
+        return Ok(Expanded(Value::Null));
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Expanded, E> {
+        return Ok(Expanded(Value::from(value)));
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Expanded, E> {
+        return Ok(Expanded(Value::from(value)));
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Expanded, E> {
+        return Ok(Expanded(Value::from(value)));
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Expanded, E> {
+        return Ok(Expanded(Value::from(value)));
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Expanded, E> {
+        return Ok(Expanded(Value::from(value)));
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Expanded, A::Error> {
+        let mut values = vec![];
+        while let Some(Expanded(value)) = seq.next_element()? {
+            values.push(value);
+        }
+        return Ok(Expanded(Value::Array(values)));
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut entries: A) -> Result<Expanded, A::Error> {
+        let mut map = Map::new();
+        while let Some((ExpandedKey(key), Expanded(value))) = entries.next_entry()? {
+            map.insert(key, value);
+        }
+        return Ok(Expanded(Value::Object(map)));
+    }
+}
+
+// a map key, sent either by name or by its index in KEYS
+struct ExpandedKey(String);
+
+impl<'de> Deserialize<'de> for ExpandedKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ExpandedKey, D::Error> {
+        return deserializer.deserialize_any(ExpandedKeyVisitor);
+    }
+}
+
+struct ExpandedKeyVisitor;
+
+impl<'de> Visitor<'de> for ExpandedKeyVisitor {
+    type Value = ExpandedKey;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a key name or index")
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, index: u64) -> Result<ExpandedKey, E> {
+        return match KEYS.get(index as usize) {
+            Some(key) => Ok(ExpandedKey(key.to_string())),
+            None => Err(E::custom(format!("unknown key index {}", index))),
+        };
+    }
+
+    fn visit_str<E>(self, key: &str) -> Result<ExpandedKey, E> {
+        return Ok(ExpandedKey(key.to_string()));
+    }
+}